@@ -1,55 +1,70 @@
 use actix_web::web::{delete, get, post, put, scope, Data as Extractor, Path, ServiceConfig};
-use actix_web::Responder;
+use actix_web::{HttpRequest, Responder};
 use actix_web_validator::{Json, QsQuery};
 
 use configuration::ama::{Ama, FilterColumns, OrderColumns};
+use services::crud::policy::Policy;
+use services::crud::traits::Crud;
 use services::db::DBConnection;
 use services::error::AppError;
+use services::guard::{AmaRead, AmaWrite, Authorized};
 use services::query_param::QueryParams;
 use services::response::Response;
 
 pub async fn ama_create_handler(
+    _: Authorized<AmaWrite>,
+    policy: Policy,
     db: Extractor<DBConnection>,
     form: Json<Ama>,
 ) -> Result<impl Responder, AppError> {
-    let new_record = form.create(&db).await?;
-    Response::result(new_record)
+    let new_record = form.create(&db, &policy).await?;
+    let location = format!("/ama/{}", new_record.id.unwrap_or_default());
+    Response::created(new_record, Some(&location))
 }
 
 pub async fn ama_get_handler(
+    _: Authorized<AmaRead>,
+    policy: Policy,
     db: Extractor<DBConnection>,
     path: Path<i32>,
 ) -> Result<impl Responder, AppError> {
     let id = path.into_inner();
-    let result = Ama::find_by_id(&db, id).await?;
-    Response::result(result)
+    let result = Ama::find_by_id(&db, id, &policy).await?;
+    Response::ok(result)
 }
 
 pub async fn ama_get_all_handler(
+    req: HttpRequest,
+    _: Authorized<AmaRead>,
+    policy: Policy,
     db: Extractor<DBConnection>,
     params: QsQuery<QueryParams<FilterColumns, OrderColumns>>,
 ) -> Result<impl Responder, AppError> {
-    let result = Ama::find(&db, params.into_inner()).await?;
-    Response::result(result)
+    let page = Ama::find(&db, params.into_inner(), &policy).await?;
+    Response::paginated(&req, page)
 }
 
 pub async fn ama_update_handler(
+    _: Authorized<AmaWrite>,
+    policy: Policy,
     db: Extractor<DBConnection>,
     path: Path<i32>,
     form: Json<Ama>,
 ) -> Result<impl Responder, AppError> {
     let id = path.into_inner();
-    form.update(&db, id).await?;
-    Response::ok()
+    form.update(&db, id, &policy).await?;
+    Response::no_content()
 }
 
 pub async fn ama_delete_handler(
+    _: Authorized<AmaWrite>,
+    policy: Policy,
     db: Extractor<DBConnection>,
     path: Path<i32>,
 ) -> Result<impl Responder, AppError> {
     let id = path.into_inner();
-    Ama::delete(&db, id).await?;
-    Response::ok()
+    Ama::delete(&db, id, &policy).await?;
+    Response::no_content()
 }
 
 pub fn routes(cfg: &mut ServiceConfig) {
@@ -0,0 +1,57 @@
+use actix_web::web::{delete, get, post, scope, Data as Extractor, Path, ServiceConfig};
+use actix_web::Responder;
+use actix_web_validator::Json;
+
+use services::api_key::{ApiKey, NewApiKey};
+use services::db::DBConnection;
+use services::error::AppError;
+use services::guard::{AccountHolder, ApiKeyManage, Authorized};
+use services::response::Response;
+
+/// the key is only returned here, store it right away
+pub async fn api_key_create_handler(
+    _: AccountHolder,
+    user: Authorized<ApiKeyManage>,
+    db: Extractor<DBConnection>,
+    form: Json<NewApiKey>,
+) -> Result<impl Responder, AppError> {
+    let issued = form.create(&db, user.0.id).await?;
+    let location = format!("/api-keys/{}", issued.id);
+    Response::created(issued, Some(&location))
+}
+
+pub async fn api_key_get_all_handler(
+    _: Authorized<ApiKeyManage>,
+    db: Extractor<DBConnection>,
+) -> Result<impl Responder, AppError> {
+    let result = ApiKey::find(&db).await?;
+    Response::ok(result)
+}
+
+pub async fn api_key_get_handler(
+    _: Authorized<ApiKeyManage>,
+    db: Extractor<DBConnection>,
+    path: Path<i32>,
+) -> Result<impl Responder, AppError> {
+    let result = ApiKey::find_by_id(&db, path.into_inner()).await?;
+    Response::ok(result)
+}
+
+pub async fn api_key_revoke_handler(
+    _: Authorized<ApiKeyManage>,
+    db: Extractor<DBConnection>,
+    path: Path<i32>,
+) -> Result<impl Responder, AppError> {
+    ApiKey::revoke(&db, path.into_inner()).await?;
+    Response::no_content()
+}
+
+pub fn routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/api-keys")
+            .route("", post().to(api_key_create_handler))
+            .route("", get().to(api_key_get_all_handler))
+            .route("/{id}", get().to(api_key_get_handler))
+            .route("/{id}", delete().to(api_key_revoke_handler)),
+    );
+}
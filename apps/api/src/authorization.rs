@@ -1,33 +1,43 @@
 use actix_web::{
-    web::{self, post},
-    Responder,
+    http::Method,
+    web::{self, get, post},
+    HttpRequest, Responder,
 };
-use actix_web_validator::Json;
+use actix_web_validator::{Json, Query};
 use serde_json::json;
 
 use authorization::forget_password::ForgetPassword;
 use authorization::login;
+use authorization::magic_link::{MagicLinkLogin, SendMagicLink};
+use authorization::oidc::{OidcCallback, OidcProvider};
+use authorization::otp::{OtpLogin, SendOtp, VerifyPhone};
 use authorization::register::RegistrationForm;
 use authorization::reset_password::ResetPassword;
+use authorization::verify_email::{ResendVerification, VerifyEmail};
+use services::client_ip::client_ip;
 use services::db::DBConnection;
 use services::error::AppError;
+use services::public_routes::{PathMatch, PublicRoutes};
+use services::rate_limit::{Limit, RateLimits};
 use services::response::Response;
 
 pub async fn login_handler(
+    req: HttpRequest,
     db: web::Data<DBConnection>,
     form: Json<login::Login>,
 ) -> Result<impl Responder, AppError> {
-    let token = form.login(&db).await?;
-    let response = json!({ "token": token });
-    Ok(web::Json(response))
+    let ip = client_ip(&req);
+    let token = form.login(&db, ip.as_deref()).await?;
+    Response::ok(json!({ "token": token }))
 }
 
 pub async fn register_handler(
     db: web::Data<DBConnection>,
     form: Json<RegistrationForm>,
 ) -> Result<impl Responder, AppError> {
-    form.register(&db).await?;
-    Response::ok()
+    let id = form.register(&db).await?;
+    // `/users/{id}` is for admins and the new user isn't logged in yet, there's nothing to point at
+    Response::created(json!({ "id": id }), None)
 }
 
 pub async fn forget_password_handler(
@@ -35,7 +45,7 @@ pub async fn forget_password_handler(
     form: Json<ForgetPassword>,
 ) -> Result<impl Responder, AppError> {
     form.attempt(&db).await?;
-    Response::ok()
+    Response::accepted()
 }
 
 pub async fn reset_password_handler(
@@ -43,16 +53,134 @@ pub async fn reset_password_handler(
     form: Json<ResetPassword>,
 ) -> Result<impl Responder, AppError> {
     form.attempt(&db).await?;
-    Response::ok()
+    Response::no_content()
+}
+
+pub async fn send_otp_handler(
+    db: web::Data<DBConnection>,
+    form: Json<SendOtp>,
+) -> Result<impl Responder, AppError> {
+    form.attempt(&db).await?;
+    Response::accepted()
+}
+
+pub async fn otp_login_handler(
+    db: web::Data<DBConnection>,
+    form: Json<OtpLogin>,
+) -> Result<impl Responder, AppError> {
+    let token = form.login(&db).await?;
+    Response::ok(json!({ "token": token }))
+}
+
+pub async fn verify_phone_handler(
+    db: web::Data<DBConnection>,
+    form: Json<VerifyPhone>,
+) -> Result<impl Responder, AppError> {
+    form.attempt(&db).await?;
+    Response::no_content()
+}
+
+pub async fn verify_email_handler(
+    db: web::Data<DBConnection>,
+    form: Json<VerifyEmail>,
+) -> Result<impl Responder, AppError> {
+    form.attempt(&db).await?;
+    Response::no_content()
+}
+
+pub async fn resend_verification_handler(
+    db: web::Data<DBConnection>,
+    form: Json<ResendVerification>,
+) -> Result<impl Responder, AppError> {
+    form.attempt(&db).await?;
+    Response::accepted()
+}
+
+pub async fn send_magic_link_handler(
+    db: web::Data<DBConnection>,
+    form: Json<SendMagicLink>,
+) -> Result<impl Responder, AppError> {
+    form.attempt(&db).await?;
+    Response::accepted()
+}
+
+pub async fn magic_link_login_handler(
+    db: web::Data<DBConnection>,
+    form: Json<MagicLinkLogin>,
+) -> Result<impl Responder, AppError> {
+    let token = form.login(&db).await?;
+    Response::ok(json!({ "token": token }))
+}
+
+/// url of the identity provider's login page, the frontend redirects the user to it
+pub async fn oidc_authorize_handler(
+    db: web::Data<DBConnection>,
+) -> Result<impl Responder, AppError> {
+    let url = OidcProvider::from_env()?.authorize_url(&db).await?;
+    Response::ok(json!({ "url": url }))
+}
+
+pub async fn oidc_callback_handler(
+    db: web::Data<DBConnection>,
+    query: Query<OidcCallback>,
+) -> Result<impl Responder, AppError> {
+    let token = query.login(&db, &OidcProvider::from_env()?).await?;
+    Response::ok(json!({ "token": token }))
 }
 
-pub fn routes(cfg: &mut web::ServiceConfig) {
+pub fn routes(cfg: &mut web::ServiceConfig, public: &mut PublicRoutes, limits: &mut RateLimits) {
+    public.prefix("/authorization");
+    // guessing passwords and codes, or flooding inboxes and phones
+    let exact = |path: &str| PathMatch::Exact(format!("/authorization{path}"));
+    limits
+        .add(
+            "login",
+            &[Method::POST],
+            exact("/login"),
+            Limit::sliding_window(10, 60),
+        )
+        .add(
+            "otp-login",
+            &[Method::POST],
+            exact("/otp/login"),
+            Limit::sliding_window(10, 60),
+        )
+        .add(
+            "forget-password",
+            &[Method::POST],
+            exact("/forget-password"),
+            Limit::token_bucket(5, 3600),
+        )
+        .add(
+            "otp-send",
+            &[Method::POST],
+            exact("/otp/send"),
+            Limit::token_bucket(5, 900),
+        )
+        .add(
+            "magic-link-send",
+            &[Method::POST],
+            exact("/magic-link/send"),
+            Limit::token_bucket(5, 900),
+        );
     cfg.service(
         // scope will add prefix to all the routes in this module
         web::scope("/authorization")
             .route("/login", post().to(login_handler))
             .route("/register", post().to(register_handler))
             .route("/forget-password", post().to(forget_password_handler))
-            .route("/reset-password", post().to(reset_password_handler)),
+            .route("/reset-password", post().to(reset_password_handler))
+            .route("/otp/send", post().to(send_otp_handler))
+            .route("/otp/login", post().to(otp_login_handler))
+            .route("/verify-phone", post().to(verify_phone_handler))
+            .route("/verify-email", post().to(verify_email_handler))
+            .route(
+                "/verify-email/resend",
+                post().to(resend_verification_handler),
+            )
+            .route("/magic-link/send", post().to(send_magic_link_handler))
+            .route("/magic-link", post().to(magic_link_login_handler))
+            .route("/oidc/authorize", get().to(oidc_authorize_handler))
+            .route("/oidc/callback", get().to(oidc_callback_handler)),
     );
 }
@@ -0,0 +1,39 @@
+use actix_web::web::{delete, post, scope, Data as Extractor, Path, ServiceConfig};
+use actix_web::Responder;
+use actix_web_validator::Json;
+use serde_json::json;
+
+use authorization::impersonate::Impersonation;
+use services::auth_user::AuthUser;
+use services::db::DBConnection;
+use services::error::AppError;
+use services::guard::{AccountHolder, Authorized, UserImpersonate};
+use services::response::Response;
+
+/// token to act as the user, valid for `IMPERSONATION_MINUTES`
+pub async fn impersonation_start_handler(
+    _: AccountHolder,
+    admin: Authorized<UserImpersonate>,
+    db: Extractor<DBConnection>,
+    path: Path<i32>,
+    form: Json<Impersonation>,
+) -> Result<impl Responder, AppError> {
+    let token = form.start(&db, &admin.0, path.into_inner()).await?;
+    Response::ok(json!({ "token": token }))
+}
+
+pub async fn impersonation_end_handler(
+    user: AuthUser,
+    db: Extractor<DBConnection>,
+) -> Result<impl Responder, AppError> {
+    Impersonation::end(&db, &user).await?;
+    Response::no_content()
+}
+
+pub fn routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/impersonation")
+            .route("", delete().to(impersonation_end_handler))
+            .route("/{user_id}", post().to(impersonation_start_handler)),
+    );
+}
@@ -1,48 +1,69 @@
 use std::env::var;
+use std::sync::Arc;
 
-use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::Method;
 use actix_web::web::get;
 use actix_web::{web, App, HttpResponse, HttpServer};
 use lambda_web::{run_actix_on_lambda, LambdaError};
-use serde_json::json;
 
 use services::error::actix_error_handler;
+use services::i18n::Localization;
+use services::logging::RequestLogger;
+use services::middleware::Authentication;
+use services::public_routes::{PathMatch, PublicRoutes};
+use services::rate_limit::memory::MemoryStore;
+use services::rate_limit::postgres::PostgresStore;
+use services::rate_limit::{RateLimitStore, RateLimiter, RateLimits};
+use services::reporting::ErrorReporting;
 
 mod ama;
+mod api_keys;
 mod authorization;
+mod impersonation;
+mod me;
+mod users;
 
 #[actix_web::main]
 async fn main() -> Result<(), LambdaError> {
     services::load_env(Some(vec!["ENC_KEY"]));
+    services::logging::init();
     // get env variable
     // create a connection pool to use in all the routes
     let db = services::db::Connection::lazy().expect("Database connection failed.");
+    // `postgres` shares the limits between instances, e.g. on Lambda
+    let store: Arc<dyn RateLimitStore> = match var("RATE_LIMIT_STORE").as_deref() {
+        Ok("postgres") => Arc::new(PostgresStore::new(db.clone())),
+        _ => Arc::new(MemoryStore::default()),
+    };
+    let sink = services::reporting::sink_from_env().expect("Invalid error reporting config.");
 
     let factory = move || {
+        // every route requires a login unless it's added here
+        let mut public = PublicRoutes::default();
+        let mut limits = RateLimits::default();
+        public.add(&[Method::GET], PathMatch::Exact("/".into()));
         App::new()
-            .wrap_fn(|request, srv| {
-                let is_ok = services::middleware::Middleware::check_login(&request);
-                if is_ok {
-                    srv.call(request)
-                } else {
-                    let response =
-                        HttpResponse::Unauthorized().json(json!({"error": "Session expired"}));
-                    let (request, _) = request.into_parts();
-                    let response = ServiceResponse::new(request, response);
-                    Box::pin(async { Ok(response) })
-                }
-            })
             .app_data(web::Data::new(db.clone()))
             .app_data(actix_error_handler())
             .route("/", get().to(HttpResponse::Ok))
             .configure(ama::routes)
-            .configure(authorization::routes)
+            .configure(api_keys::routes)
+            .configure(|cfg| authorization::routes(cfg, &mut public, &mut limits))
+            .configure(impersonation::routes)
+            .configure(|cfg| me::routes(cfg, &mut limits))
+            .configure(users::routes)
+            // limits keyed by user need the claim, so the authentication runs first
+            .wrap(RateLimiter::new(limits, store.clone()))
+            .wrap(Authentication::new(public))
+            .wrap(Localization)
+            .wrap(ErrorReporting::new(sink.clone()))
+            .wrap(RequestLogger)
     };
     if var("LAMBDA_RUNTIME_API").is_ok() {
         // Run on AWS Lambda
         run_actix_on_lambda(factory).await?
     } else {
-        println!("app started http://127.0.0.1:8080");
+        tracing::info!("app started http://127.0.0.1:8080");
         // Run local server
         HttpServer::new(factory)
             .bind(("0.0.0.0", 8080))?
@@ -0,0 +1,58 @@
+use actix_web::http::Method;
+use actix_web::web::{get, patch, post, scope, Data as Extractor, ServiceConfig};
+use actix_web::{HttpRequest, Responder};
+use actix_web_validator::Json;
+
+use authorization::profile::{ChangePassword, Profile, UpdateProfile};
+use services::auth_user::AuthUser;
+use services::client_ip::client_ip;
+use services::db::DBConnection;
+use services::error::AppError;
+use services::guard::AccountHolder;
+use services::public_routes::PathMatch;
+use services::rate_limit::{KeyBy, Limit, RateLimits};
+use services::response::Response;
+
+pub async fn me_get_handler(
+    user: AuthUser,
+    db: Extractor<DBConnection>,
+) -> Result<impl Responder, AppError> {
+    let result = Profile::find(&db, user.id).await?;
+    Response::ok(result)
+}
+
+pub async fn me_update_handler(
+    user: AccountHolder,
+    db: Extractor<DBConnection>,
+    form: Json<UpdateProfile>,
+) -> Result<impl Responder, AppError> {
+    let result = form.attempt(&db, user.0.id).await?;
+    Response::ok(result)
+}
+
+pub async fn me_password_handler(
+    req: HttpRequest,
+    user: AccountHolder,
+    db: Extractor<DBConnection>,
+    form: Json<ChangePassword>,
+) -> Result<impl Responder, AppError> {
+    let ip = client_ip(&req);
+    form.attempt(&db, &user.0, ip.as_deref()).await?;
+    Response::no_content()
+}
+
+pub fn routes(cfg: &mut ServiceConfig, limits: &mut RateLimits) {
+    // guessing the current password with a stolen session
+    limits.add(
+        "me-password",
+        &[Method::POST],
+        PathMatch::Exact("/me/password".into()),
+        Limit::sliding_window(5, 900).key_by(KeyBy::User),
+    );
+    cfg.service(
+        scope("/me")
+            .route("", get().to(me_get_handler))
+            .route("", patch().to(me_update_handler))
+            .route("/password", post().to(me_password_handler)),
+    );
+}
@@ -0,0 +1,80 @@
+use actix_web::web::{get, patch, post, scope, Data as Extractor, Path, ServiceConfig};
+use actix_web::{HttpRequest, Responder};
+use actix_web_validator::{Json, QsQuery};
+
+use authorization::profile::Profile;
+use authorization::users::{
+    ChangeStatus, FilterColumns, NewUser, OrderColumns, UpdateUser, UserList,
+};
+use services::db::DBConnection;
+use services::error::AppError;
+use services::guard::{AccountHolder, Authorized, UserManage};
+use services::query_param::QueryParams;
+use services::response::Response;
+
+/// only an admin signed in as themselves can create admins
+pub async fn users_create_handler(
+    _: Authorized<UserManage>,
+    holder: Option<AccountHolder>,
+    db: Extractor<DBConnection>,
+    form: Json<NewUser>,
+) -> Result<impl Responder, AppError> {
+    let result = form.create(&db, holder.as_ref()).await?;
+    let location = format!("/users/{}", result.id);
+    Response::created(result, Some(&location))
+}
+
+pub async fn users_get_handler(
+    _: Authorized<UserManage>,
+    db: Extractor<DBConnection>,
+    path: Path<i32>,
+) -> Result<impl Responder, AppError> {
+    let id = path.into_inner();
+    let result = Profile::find(&db, id).await?;
+    Response::ok(result)
+}
+
+pub async fn users_get_all_handler(
+    req: HttpRequest,
+    _: Authorized<UserManage>,
+    db: Extractor<DBConnection>,
+    params: QsQuery<QueryParams<FilterColumns, OrderColumns>>,
+) -> Result<impl Responder, AppError> {
+    let page = UserList::find(&db, params.into_inner()).await?;
+    Response::paginated(&req, page)
+}
+
+/// only an admin signed in as themselves can change the type to or from Admin
+pub async fn users_update_handler(
+    _: Authorized<UserManage>,
+    holder: Option<AccountHolder>,
+    db: Extractor<DBConnection>,
+    path: Path<i32>,
+    form: Json<UpdateUser>,
+) -> Result<impl Responder, AppError> {
+    let id = path.into_inner();
+    let result = form.update(&db, id, holder.as_ref()).await?;
+    Response::ok(result)
+}
+
+pub async fn users_status_handler(
+    admin: Authorized<UserManage>,
+    db: Extractor<DBConnection>,
+    path: Path<i32>,
+    form: Json<ChangeStatus>,
+) -> Result<impl Responder, AppError> {
+    let id = path.into_inner();
+    let result = form.attempt(&db, id, &admin.0).await?;
+    Response::ok(result)
+}
+
+pub fn routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/users")
+            .route("", post().to(users_create_handler))
+            .route("", get().to(users_get_all_handler))
+            .route("/{id}", get().to(users_get_handler))
+            .route("/{id}", patch().to(users_update_handler))
+            .route("/{id}/status", post().to(users_status_handler)),
+    );
+}
@@ -1,22 +1,42 @@
 use aws_lambda_events::sqs::SqsEvent;
 use lambda_runtime::{service_fn, Error, LambdaEvent};
+use tracing::Instrument;
 
 use services::db::DBConnection;
 use services::queue::Message;
+use services::reporting::{self, with_breadcrumbs, ErrorReport, ErrorSink};
 
 async fn process_message(_db: &DBConnection, body: &str) -> Result<(), Error> {
     let _message = serde_json::from_str::<Message>(body)?;
     Ok(())
 }
 
-async fn sqs_event_handler(db: &DBConnection, event: LambdaEvent<SqsEvent>) -> Result<(), Error> {
+async fn sqs_event_handler(
+    db: &DBConnection,
+    sink: &dyn ErrorSink,
+    event: LambdaEvent<SqsEvent>,
+) -> Result<(), Error> {
     for record in event.payload.records {
         if let Some(body) = &record.body {
-            let result = process_message(db, body).await;
-            if let Err(e) = result {
-                // todo:: Notify Sentry
-                println!("[Error] {e} - {body}");
-            }
+            // logs of the message share the id of the api request that queued it
+            let request_id = serde_json::from_str::<Message>(body)
+                .ok()
+                .and_then(|message| message.request_id);
+            let span = tracing::info_span!(
+                "message",
+                request_id = request_id.as_deref(),
+                message_id = record.message_id.as_deref()
+            );
+            let handle = async {
+                let Err(e) = process_message(db, body).await else {
+                    return;
+                };
+                tracing::error!("{e} - {body}");
+                let mut report = ErrorReport::new(e.to_string()).extra("body", body.as_str());
+                report.request_id = request_id.clone();
+                reporting::report(sink, report).await;
+            };
+            with_breadcrumbs(handle).instrument(span).await;
         }
     }
     Ok(())
@@ -24,18 +44,16 @@ async fn sqs_event_handler(db: &DBConnection, event: LambdaEvent<SqsEvent>) -> R
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .with_target(false)
-        .without_time()
-        .init();
+    services::logging::init();
 
     // create shared db connection pool
     let db = services::db::Connection::lazy()?;
     let db_pool = &db;
+    let sink = reporting::sink_from_env()?;
+    let sink = sink.as_ref();
     // run event handler with shared db pool as ref
     lambda_runtime::run(service_fn(move |event: LambdaEvent<SqsEvent>| async move {
-        sqs_event_handler(db_pool, event).await
+        sqs_event_handler(db_pool, sink, event).await
     }))
     .await?;
     Ok(())
@@ -0,0 +1,6 @@
+BEGIN;
+-- password: password123
+INSERT INTO users (first_name, last_name, user_name, email, password, phone, type, state, country, phone_verified_at)
+VALUES ('Hubert', 'Humphrey', 'hubert', 'hubert@hgicrusade.com',
+        '$2a$12$aLXb.MdMXGf6.GbP00Fenupmm9tl3IvwCnnZu0qjDmvjs98ocuoHO', '+17786866393', 'Associate', 'GA', 'US', NOW());
+COMMIT;
@@ -0,0 +1,187 @@
+use chrono::{Duration, Utc};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use services::db::DBConnection;
+use services::env_or;
+use services::error::AppError;
+use services::middleware::UserClaim;
+use services::session::Session;
+use services::users::UserType;
+use AppError::Response;
+
+use crate::login::user_claim;
+
+/// minutes an impersonation token is valid, `IMPERSONATION_MINUTES`
+const DEFAULT_IMPERSONATION_MINUTES: i64 = 30;
+
+/// Admin signed in as another user, every one is recorded in the `impersonations` table.
+#[derive(Serialize, Deserialize, Validate)]
+pub struct Impersonation {
+    #[validate(length(max = 500, message = "impersonation-reason-length"))]
+    pub reason: Option<String>,
+}
+
+impl Impersonation {
+    /// time limited token of `user_id` with the admin in `impersonated_by`.
+    /// admins can't be impersonated and an impersonated session can't start another one.
+    pub async fn start(
+        &self,
+        db: &DBConnection,
+        admin: &UserClaim,
+        user_id: i32,
+    ) -> Result<String, AppError> {
+        if !admin.is_admin() || admin.impersonated_by.is_some() {
+            return Err(Response(
+                "impersonation-admins-only".into(),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+        let user = sqlx::query!(
+            r#"SELECT type AS "user_type: UserType" FROM users WHERE id = $1"#,
+            user_id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User".into()))?;
+        if user.user_type == UserType::Admin {
+            return Err(Response(
+                "impersonation-admin-target".into(),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+        let minutes = env_or("IMPERSONATION_MINUTES", DEFAULT_IMPERSONATION_MINUTES);
+        let expires_at = (Utc::now() + Duration::minutes(minutes)).naive_utc();
+        let mut claim = user_claim(db, user_id, expires_at).await?;
+        claim.impersonated_by = Some(admin.id);
+        sqlx::query!(
+            r#"
+                INSERT INTO impersonations (admin_id, user_id, session_id, reason)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            admin.id,
+            user_id,
+            claim.sid,
+            self.reason
+        )
+        .execute(db)
+        .await?;
+        services::encryption::Jwt::encode(&claim)
+    }
+
+    /// end the impersonation of the current token, the admin goes back to their own token
+    pub async fn end(db: &DBConnection, user: &UserClaim) -> Result<(), AppError> {
+        if user.impersonated_by.is_none() {
+            return Err(Response(
+                "impersonation-not-active".into(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        sqlx::query!(
+            "UPDATE impersonations SET ended_at = NOW() WHERE session_id = $1 AND ended_at IS NULL",
+            user.sid
+        )
+        .execute(db)
+        .await?;
+        Session::revoke(db, user.sid).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use services::db::DBConnection;
+    use services::encryption::Jwt;
+    use services::error::AppError;
+    use services::load_env;
+    use services::middleware::UserClaim;
+    use services::session::Session;
+    use services::users::UserType;
+
+    use super::Impersonation;
+
+    fn admin() -> UserClaim {
+        UserClaim::new(
+            99,
+            1,
+            UserType::Admin,
+            "Sam".into(),
+            "Rusty".into(),
+            "sam@hgicrusade.com".into(),
+            None,
+        )
+    }
+
+    async fn hubert(pool: &DBConnection) -> i32 {
+        sqlx::query_scalar!("SELECT id FROM users WHERE user_name = 'hubert'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_impersonate_and_end(pool: DBConnection) {
+        load_env(None);
+        let admin_id = sqlx::query_scalar!(
+            r#"
+                INSERT INTO users (first_name, last_name, user_name, email, password, phone, type, state, country)
+                VALUES ('Sam', 'Rusty', 'sam', 'sam@hgicrusade.com', '-', '+17780000000', 'Admin', 'GA', 'US')
+                RETURNING id
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let mut admin = admin();
+        admin.id = admin_id;
+        let user_id = hubert(&pool).await;
+        let form = Impersonation {
+            reason: Some("ticket 42".into()),
+        };
+        let token = form.start(&pool, &admin, user_id).await.unwrap();
+        let claim = Jwt::decode::<UserClaim>(&token).unwrap();
+        assert_eq!(claim.id, user_id);
+        assert_eq!(claim.impersonated_by, Some(admin_id));
+        assert!(!claim.is_admin());
+
+        Impersonation::end(&pool, &claim).await.unwrap();
+        assert!(!Session::is_active(&pool, claim.sid).await.unwrap());
+        let ended = sqlx::query_scalar!(
+            r#"SELECT ended_at IS NOT NULL AS "ended!" FROM impersonations WHERE session_id = $1"#,
+            claim.sid
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(ended);
+        // the audit stays when the admin is removed
+        sqlx::query!("DELETE FROM users WHERE id = $1", admin_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let audited = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM impersonations WHERE user_id = $1 AND admin_id IS NULL"#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(audited, 1);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_not_impersonate_from_impersonated_session(pool: DBConnection) {
+        let mut admin = admin();
+        admin.impersonated_by = Some(1);
+        let user_id = hubert(&pool).await;
+        let result = Impersonation { reason: None }
+            .start(&pool, &admin, user_id)
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::FORBIDDEN))
+        ));
+    }
+}
@@ -1,4 +1,14 @@
 pub mod forget_password;
+pub mod impersonate;
+pub mod lockout;
 pub mod login;
+pub mod magic_link;
+pub mod oidc;
+pub mod otp;
+pub mod password_policy;
+pub mod profile;
 pub mod register;
 pub mod reset_password;
+pub mod single_use_token;
+pub mod users;
+pub mod verify_email;
@@ -0,0 +1,245 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use http::StatusCode;
+
+use integration::sendgrid::Recipient;
+use services::db::DBConnection;
+use services::env_or;
+use services::error::AppError;
+use services::i18n::t_args;
+use AppError::Response;
+
+use crate::single_use_token::{SingleUseToken, TokenPurpose};
+
+/// failed logins before an account is locked, `LOGIN_MAX_ATTEMPTS`
+const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+/// minutes an account stays locked, `LOGIN_LOCKOUT_MINUTES`
+const DEFAULT_LOCKOUT_MINUTES: i64 = 15;
+/// failed logins from one ip within the lockout window, `LOGIN_MAX_ATTEMPTS_PER_IP`
+const DEFAULT_MAX_ATTEMPTS_PER_IP: i64 = 20;
+/// failures allowed before the progressive delay kicks in
+const FREE_ATTEMPTS: i32 = 2;
+/// longest wait between two attempts on the same account
+const MAX_DELAY_SECONDS: i64 = 60;
+/// days the attempts are kept, older ones are pruned
+const ATTEMPT_RETENTION_DAYS: i32 = 30;
+
+pub struct LockoutPolicy {
+    pub max_attempts: i32,
+    pub lockout_minutes: i64,
+    pub max_attempts_per_ip: i64,
+}
+
+impl LockoutPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            max_attempts: env_or("LOGIN_MAX_ATTEMPTS", DEFAULT_MAX_ATTEMPTS),
+            lockout_minutes: env_or("LOGIN_LOCKOUT_MINUTES", DEFAULT_LOCKOUT_MINUTES),
+            max_attempts_per_ip: env_or("LOGIN_MAX_ATTEMPTS_PER_IP", DEFAULT_MAX_ATTEMPTS_PER_IP),
+        }
+    }
+
+    /// seconds to wait after the last failure, doubles with every failure after the free ones
+    pub fn delay_seconds(failed_count: i32) -> i64 {
+        if failed_count <= FREE_ATTEMPTS {
+            return 0;
+        }
+        let exponent = (failed_count - FREE_ATTEMPTS - 1).min(6) as u32;
+        2_i64.pow(exponent).min(MAX_DELAY_SECONDS)
+    }
+
+    /// reject the attempt when the client ip has failed too many logins recently
+    pub async fn check_ip(&self, db: &DBConnection, ip: Option<&str>) -> Result<(), AppError> {
+        let Some(ip) = ip else {
+            return Ok(());
+        };
+        let failures = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!" FROM login_attempts
+                WHERE ip = $1 AND success = FALSE AND created_at > NOW() - make_interval(mins => $2)
+            "#,
+            ip,
+            self.lockout_minutes as i32
+        )
+        .fetch_one(db)
+        .await?;
+        if failures >= self.max_attempts_per_ip {
+            return Err(Response(
+                "too-many-login-attempts".into(),
+                StatusCode::TOO_MANY_REQUESTS,
+            ));
+        }
+        Ok(())
+    }
+
+    /// reject the attempt when the account is locked or still within its progressive delay
+    pub fn check_account(
+        &self,
+        failed_count: i32,
+        last_failed_at: Option<NaiveDateTime>,
+        locked_until: Option<NaiveDateTime>,
+    ) -> Result<(), AppError> {
+        let now = Utc::now().naive_utc();
+        if locked_until.is_some_and(|until| until > now) {
+            return Err(Response(
+                "account-locked-try-later".into(),
+                StatusCode::LOCKED,
+            ));
+        }
+        if let Some(last_failed_at) = last_failed_at {
+            let wait = last_failed_at + Duration::seconds(Self::delay_seconds(failed_count)) - now;
+            if wait > Duration::zero() {
+                return Err(Response(
+                    t_args(
+                        "wait-before-retry",
+                        &[("seconds", (wait.num_seconds() + 1).to_string())],
+                    ),
+                    StatusCode::TOO_MANY_REQUESTS,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// count the failure against the user name and ip, lock the account once the threshold is hit.
+    /// returns true when this failure locked the account.
+    pub async fn record_failure(
+        &self,
+        db: &DBConnection,
+        user_name: &str,
+        ip: Option<&str>,
+    ) -> Result<bool, AppError> {
+        Self::record_attempt(db, user_name, ip, false).await?;
+        let user = sqlx::query!(
+            r#"
+                WITH current AS (
+                    -- counting starts over once a previous lock has expired
+                    SELECT
+                        id,
+                        CASE WHEN locked_until <= NOW() THEN 1 ELSE failed_login_count + 1 END AS failed
+                    FROM users
+                    WHERE user_name = $1
+                )
+                UPDATE users SET
+                    failed_login_count = current.failed,
+                    last_failed_login_at = NOW(),
+                    locked_until = CASE
+                        WHEN current.failed >= $2 THEN NOW() + make_interval(mins => $3)
+                    END
+                FROM current
+                WHERE users.id = current.id
+                RETURNING users.id, first_name, last_name, email, failed_login_count
+            "#,
+            user_name,
+            self.max_attempts,
+            self.lockout_minutes as i32
+        )
+        .fetch_optional(db)
+        .await?;
+        let Some(user) = user else {
+            return Ok(false);
+        };
+        // only notify on the failure that locks the account
+        if user.failed_login_count != self.max_attempts {
+            return Ok(false);
+        }
+        let link = SingleUseToken::link(db, user.id, TokenPurpose::PasswordReset).await?;
+        let message = format!(
+            r#"
+                <p>Hi {} {},</p>
+                <p>Your account was locked for {} minutes after {} failed login attempts.</p>
+                <p>If this wasn't you, please <a href='{link}'>reset your password</a> to unlock your account.</p>
+            "#,
+            user.first_name, user.last_name, self.lockout_minutes, self.max_attempts
+        );
+        let email = integration::sendgrid::Email::new(
+            Recipient::new(user.email, user.first_name, user.last_name),
+            "Your Account Has Been Locked",
+            message,
+        );
+        // the account stays locked even if the notification can't be sent
+        if let Err(e) = email.send().await {
+            tracing::error!("{e} - lockout notification {user_name}");
+        }
+        Ok(true)
+    }
+
+    pub async fn record_success(
+        &self,
+        db: &DBConnection,
+        user_name: &str,
+        ip: Option<&str>,
+    ) -> Result<(), AppError> {
+        Self::record_attempt(db, user_name, ip, true).await?;
+        sqlx::query!(
+            r#"
+                UPDATE users SET failed_login_count = 0, last_failed_login_at = NULL, locked_until = NULL
+                WHERE user_name = $1
+            "#,
+            user_name
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// attempts older than the retention are pruned now and then, only the recent ones are counted
+    async fn record_attempt(
+        db: &DBConnection,
+        user_name: &str,
+        ip: Option<&str>,
+        success: bool,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            "INSERT INTO login_attempts (user_name, ip, success) VALUES ($1, $2, $3)",
+            user_name,
+            ip,
+            success
+        )
+        .execute(db)
+        .await?;
+        if rand::random::<u8>() == 0 {
+            Self::prune(db).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn prune(db: &DBConnection) -> Result<u64, AppError> {
+        let deleted = sqlx::query!(
+            "DELETE FROM login_attempts WHERE created_at < NOW() - make_interval(days => $1)",
+            ATTEMPT_RETENTION_DAYS
+        )
+        .execute(db)
+        .await?
+        .rows_affected();
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use services::db::DBConnection;
+
+    use super::LockoutPolicy;
+
+    #[test]
+    fn should_double_delay_after_free_attempts() {
+        let delays: Vec<i64> = (0..6).map(LockoutPolicy::delay_seconds).collect();
+        assert_eq!(delays, vec![0, 0, 0, 1, 2, 4]);
+        assert_eq!(LockoutPolicy::delay_seconds(100), 60);
+    }
+
+    #[sqlx::test(migrations = "../../migrations")]
+    async fn should_prune_old_attempts(pool: DBConnection) {
+        sqlx::query!(
+            r#"
+                INSERT INTO login_attempts (user_name, ip, success, created_at)
+                VALUES ('hubert', '203.0.113.7', FALSE, NOW() - INTERVAL '31 days'),
+                       ('hubert', '203.0.113.7', FALSE, NOW())
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(LockoutPolicy::prune(&pool).await.unwrap(), 1);
+    }
+}
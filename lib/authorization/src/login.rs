@@ -1,58 +1,258 @@
-use bcrypt::verify;
+use chrono::NaiveDateTime;
 use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
 use services::db::DBConnection;
 use services::error::AppError;
+use services::i18n::Locale;
 use services::middleware::UserClaim;
+use services::password::PasswordHasher;
+use services::session::Session;
+use services::users::{UserStatus, UserType};
 use AppError::Response;
 
+use crate::lockout::LockoutPolicy;
+
 #[derive(Serialize, Deserialize, Validate)]
 pub struct Login {
     user_name: String,
-    #[validate(length(min = 8, message = "Password must be at least 8 characters long"))]
+    #[validate(length(min = 8, message = "password-min-length"))]
     password: String,
 }
 
 impl Login {
-    pub async fn login(&self, db: &DBConnection) -> Result<String, AppError> {
+    /// `ip` is the client address used to throttle attempts across accounts.
+    pub async fn login(&self, db: &DBConnection, ip: Option<&str>) -> Result<String, AppError> {
+        let policy = LockoutPolicy::from_env();
+        policy.check_ip(db, ip).await?;
         // get account information
         let user = sqlx::query!(
             r#"
                 SELECT
-                    first_name, last_name, email, password, photo
+                    id, password, email_verified_at,
+                    failed_login_count, last_failed_login_at, locked_until
                 FROM users
                 WHERE user_name = $1
             "#,
             self.user_name
         )
-        .fetch_one(db)
-        .await
-        .map_err(|_| {
-            Response(
-                "Invalid username or password".to_string(),
+        .fetch_optional(db)
+        .await?;
+        let Some(user) = user else {
+            policy.record_failure(db, &self.user_name, ip).await?;
+            return Err(Response(
+                "invalid-credentials".to_string(),
                 StatusCode::UNAUTHORIZED,
-            )
-        })?;
+            ));
+        };
+        policy.check_account(
+            user.failed_login_count,
+            user.last_failed_login_at,
+            user.locked_until,
+        )?;
 
         // verify password hash
-        if !verify(&self.password, &user.password).unwrap_or(false) {
+        let hasher = PasswordHasher::from_env()?;
+        if !hasher.verify(&self.password, &user.password) {
+            if policy.record_failure(db, &self.user_name, ip).await? {
+                return Err(Response("account-locked".into(), StatusCode::LOCKED));
+            }
             return Err(Response(
-                "Invalid username or password".into(),
+                "invalid-credentials".into(),
                 StatusCode::UNAUTHORIZED,
             ));
         }
+        crate::verify_email::ensure_verified(user.email_verified_at)?;
+        policy.record_success(db, &self.user_name, ip).await?;
+        if hasher.needs_rehash(&user.password) {
+            if let Err(e) = Self::rehash(db, &hasher, user.id, &self.password).await {
+                tracing::error!("{e} - password rehash {}", user.id);
+            }
+        }
+        issue_token(db, user.id).await
+    }
+
+    /// replace a hash made with an outdated algorithm or parameters, only possible while the password is known
+    async fn rehash(
+        db: &DBConnection,
+        hasher: &PasswordHasher,
+        user_id: i32,
+        password: &str,
+    ) -> Result<(), AppError> {
+        let password = hasher.hash(password)?;
+        sqlx::query!(
+            "UPDATE users SET password = $1 WHERE id = $2",
+            password,
+            user_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
+
+/// start a session for an authenticated user and return its signed `UserClaim` token.
+pub(crate) async fn issue_token(db: &DBConnection, user_id: i32) -> Result<String, AppError> {
+    let claim = user_claim(db, user_id, UserClaim::expires_at()).await?;
+    services::encryption::Jwt::encode(&claim)
+}
 
-        let token = services::encryption::Jwt::encode(&UserClaim::new(
-            user.first_name,
-            user.last_name,
-            user.email,
-            user.photo,
-        ))?;
-        Ok(token)
+/// claim with a new session that ends at `expires_at`, users that aren't active can't log in.
+/// roles are the one named after the user type plus any assigned in `user_roles`.
+pub(crate) async fn user_claim(
+    db: &DBConnection,
+    user_id: i32,
+    expires_at: NaiveDateTime,
+) -> Result<UserClaim, AppError> {
+    let user = sqlx::query!(
+        r#"
+            SELECT
+                type AS "user_type: UserType", status AS "status: UserStatus",
+                first_name, last_name, email, photo, locale AS "locale: Locale"
+            FROM users
+            WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("User".into()))?;
+    if !user.status.is_active() {
+        return Err(Response("account-not-active".into(), StatusCode::FORBIDDEN));
     }
+    let roles = sqlx::query_scalar!(
+        r#"
+            SELECT r.name FROM roles r
+            WHERE r.name = $2
+               OR EXISTS(SELECT 1 FROM user_roles ur WHERE ur.role_id = r.id AND ur.user_id = $1)
+            ORDER BY r.name
+        "#,
+        user_id,
+        user.user_type.as_ref()
+    )
+    .fetch_all(db)
+    .await?;
+    let permissions = sqlx::query_scalar!(
+        r#"
+            SELECT DISTINCT p.name FROM permissions p
+            JOIN role_permissions rp ON rp.permission_id = p.id
+            JOIN roles r ON r.id = rp.role_id
+            WHERE r.name = ANY($1)
+            ORDER BY p.name
+        "#,
+        &roles
+    )
+    .fetch_all(db)
+    .await?;
+    let sid = Session::create(db, user_id, expires_at).await?;
+    let mut claim = UserClaim::new(
+        user_id,
+        sid,
+        user.user_type,
+        user.first_name,
+        user.last_name,
+        user.email,
+        user.photo,
+    )
+    .with_access(roles, permissions);
+    claim.exp = expires_at.timestamp();
+    claim.locale = user.locale;
+    Ok(claim)
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use http::StatusCode;
+
+    use services::db::DBConnection;
+    use services::encryption::Jwt;
+    use services::error::AppError;
+    use services::load_env;
+    use services::middleware::UserClaim;
+    use services::users::UserType;
+
+    use super::Login;
+
+    fn login(password: &str) -> Login {
+        Login {
+            user_name: "hubert".into(),
+            password: password.into(),
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_lock_after_max_attempts(pool: DBConnection) {
+        load_env(None);
+        // skip the progressive delay of the earlier failures
+        sqlx::query!("UPDATE users SET failed_login_count = 4 WHERE user_name = 'hubert'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let result = login("wrong-password").login(&pool, None).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::LOCKED))
+        ));
+        // the right password is refused while locked
+        let result = login("password123").login(&pool, None).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::LOCKED))
+        ));
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_include_access_in_token(pool: DBConnection) {
+        load_env(None);
+        let token = login("password123").login(&pool, None).await.unwrap();
+        let claim = Jwt::decode::<UserClaim>(&token).unwrap();
+        assert_eq!(claim.user_type, UserType::Associate);
+        assert_eq!(claim.roles, vec!["Associate".to_string()]);
+        assert!(claim.can("ama.read"));
+        assert!(!claim.can("ama.write"));
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_reset_failures_on_success(pool: DBConnection) {
+        load_env(None);
+        assert!(login("wrong-password").login(&pool, None).await.is_err());
+        assert!(login("password123").login(&pool, None).await.is_ok());
+        let failed =
+            sqlx::query_scalar!("SELECT failed_login_count FROM users WHERE user_name = 'hubert'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(failed, 0);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_rehash_legacy_passwords(pool: DBConnection) {
+        load_env(None);
+        let password = || async {
+            sqlx::query_scalar!("SELECT password FROM users WHERE user_name = 'hubert'")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+        assert!(password().await.starts_with("$2a$"));
+        assert!(login("password123").login(&pool, None).await.is_ok());
+        assert!(password().await.starts_with("$argon2id$"));
+        // the new hash still verifies
+        assert!(login("password123").login(&pool, None).await.is_ok());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_refuse_inactive_users(pool: DBConnection) {
+        load_env(None);
+        sqlx::query!("UPDATE users SET status = 'Terminated' WHERE user_name = 'hubert'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let result = login("password123").login(&pool, None).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::FORBIDDEN))
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use validator::Validate as ActixValidator;
+
+use integration::sendgrid::Recipient;
+use services::db::DBConnection;
+use services::error::AppError;
+use services::users::UserStatus;
+use AppError::Response;
+
+use crate::lockout::LockoutPolicy;
+use crate::login::issue_token;
+use crate::single_use_token::{SingleUseToken, TokenPurpose};
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct SendMagicLink {
+    #[validate(email)]
+    pub email: String,
+}
+
+impl SendMagicLink {
+    /// email a login link to active users. unknown emails and failures to send get the
+    /// same response so the endpoint can't be used to look up registered emails.
+    pub async fn attempt(&self, db: &DBConnection) -> Result<(), AppError> {
+        let user = sqlx::query!(
+            r#"
+                SELECT id, first_name, last_name, email FROM users
+                WHERE email = $1 AND status = 'Active'
+            "#,
+            self.email
+        )
+        .fetch_optional(db)
+        .await?;
+        let Some(user) = user else {
+            return Ok(());
+        };
+        let link = SingleUseToken::link(db, user.id, TokenPurpose::MagicLink).await?;
+        let message = format!(
+            r#"
+                <p>Hi {} {},</p>
+                <p>Please <a href='{link}'>click here</a> to log in. The link can only be used once.</p>
+            "#,
+            user.first_name, user.last_name
+        );
+        let email = integration::sendgrid::Email::new(
+            Recipient::new(user.email, user.first_name, user.last_name),
+            "Your Login Link",
+            message,
+        );
+        if let Err(e) = email.send().await {
+            tracing::error!("{e} - magic link {}", user.id);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct MagicLinkLogin {
+    pub token: String,
+}
+
+impl MagicLinkLogin {
+    /// exchange the link for a token, the link proves the user owns the email so it's marked as verified.
+    /// the account is checked like a password login first, the link stays usable when it's rejected.
+    pub async fn login(&self, db: &DBConnection) -> Result<String, AppError> {
+        let mut tx = db.begin().await?;
+        let user_id = SingleUseToken::consume(&mut *tx, TokenPurpose::MagicLink, &self.token)
+            .await?
+            .ok_or_else(|| Response("invalid-link".into(), StatusCode::BAD_REQUEST))?;
+        let user = sqlx::query!(
+            r#"
+                SELECT
+                    status AS "status: UserStatus",
+                    failed_login_count, last_failed_login_at, locked_until
+                FROM users
+                WHERE id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if !user.status.is_active() {
+            return Err(Response("account-not-active".into(), StatusCode::FORBIDDEN));
+        }
+        LockoutPolicy::from_env().check_account(
+            user.failed_login_count,
+            user.last_failed_login_at,
+            user.locked_until,
+        )?;
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        issue_token(db, user_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use services::db::DBConnection;
+    use services::encryption::Jwt;
+    use services::error::AppError;
+    use services::load_env;
+    use services::middleware::UserClaim;
+
+    use crate::single_use_token::{SingleUseToken, TokenPurpose};
+
+    use super::{MagicLinkLogin, SendMagicLink};
+
+    fn form(link: &str) -> MagicLinkLogin {
+        MagicLinkLogin {
+            token: link.rsplit('/').next().unwrap().to_string(),
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_login_once_with_latest_link(pool: DBConnection) {
+        load_env(None);
+        let first = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::MagicLink)
+                .await
+                .unwrap(),
+        );
+        let second = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::MagicLink)
+                .await
+                .unwrap(),
+        );
+        assert!(first.login(&pool).await.is_err());
+        let token = second.login(&pool).await.unwrap();
+        assert_eq!(Jwt::decode::<UserClaim>(&token).unwrap().id, 1);
+        assert!(second.login(&pool).await.is_err());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_respond_the_same_for_unknown_emails(pool: DBConnection) {
+        let unknown = SendMagicLink {
+            email: "nobody@hgicrusade.com".into(),
+        };
+        assert!(unknown.attempt(&pool).await.is_ok());
+        let known = SendMagicLink {
+            email: "hubert@hgicrusade.com".into(),
+        };
+        assert!(known.attempt(&pool).await.is_ok());
+        let links = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM single_use_tokens WHERE purpose = 'MagicLink'"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(links, 1);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_check_account_before_login(pool: DBConnection) {
+        load_env(None);
+        let link = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::MagicLink)
+                .await
+                .unwrap(),
+        );
+        sqlx::query!("UPDATE users SET locked_until = NOW() + INTERVAL '5 minutes' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let result = link.login(&pool).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::LOCKED))
+        ));
+        sqlx::query!("UPDATE users SET locked_until = NULL, status = 'Inactive' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let result = link.login(&pool).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::FORBIDDEN))
+        ));
+        let verified_at = sqlx::query_scalar!("SELECT email_verified_at FROM users WHERE id = 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(verified_at.is_none());
+
+        // the link wasn't used up by the rejected attempts
+        sqlx::query!("UPDATE users SET status = 'Active' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(link.login(&pool).await.is_ok());
+    }
+}
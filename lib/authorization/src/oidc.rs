@@ -0,0 +1,660 @@
+use std::collections::HashMap;
+use std::env::var;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration as StdDuration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use http::StatusCode;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use validator::Validate;
+
+use services::db::DBConnection;
+use services::encryption::Token;
+use services::error::AppError;
+use services::users::UserStatus;
+use AppError::Response;
+
+use crate::lockout::LockoutPolicy;
+use crate::login::issue_token;
+
+/// minutes the user has to finish signing in at the provider
+const LOGIN_REQUEST_TTL_MINUTES: i64 = 10;
+/// seconds the discovery document and JWKS are reused before being fetched again
+const METADATA_TTL_SECONDS: u64 = 3600;
+/// seconds the provider has to answer before the login fails
+const TIMEOUT_SECONDS: u64 = 10;
+/// id tokens signed otherwise are rejected, whatever their header says
+const ALLOWED_ALGORITHMS: [Algorithm; 2] = [Algorithm::RS256, Algorithm::ES256];
+
+/// OpenID Connect provider the users sign in with, configured by the `OIDC_*` env vars.
+pub struct OidcProvider {
+    pub issuer: String,
+    pub client_id: String,
+    /// not needed for public clients, PKCE protects the code exchange
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+}
+
+/// `.well-known/openid-configuration` of the provider
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    nonce: Option<String>,
+}
+
+fn login_failed() -> AppError {
+    Response("oidc-login-failed".into(), StatusCode::UNAUTHORIZED)
+}
+
+fn unavailable(e: reqwest::Error) -> AppError {
+    AppError::MessageWithCause("Identity provider is unavailable".into(), e.to_string())
+}
+
+fn client() -> Result<Client, AppError> {
+    Client::builder()
+        .timeout(StdDuration::from_secs(TIMEOUT_SECONDS))
+        .build()
+        .map_err(|e| AppError::Message(e.to_string()))
+}
+
+impl OidcProvider {
+    pub fn from_env() -> Result<Self, AppError> {
+        Ok(Self {
+            issuer: var("OIDC_ISSUER")?,
+            client_id: var("OIDC_CLIENT_ID")?,
+            client_secret: var("OIDC_CLIENT_SECRET").ok().filter(|s| !s.is_empty()),
+            redirect_uri: var("OIDC_REDIRECT_URI")?,
+        })
+    }
+
+    async fn discover(&self) -> Result<Discovery, AppError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = Self::get(&url, false).await?;
+        if discovery.issuer != self.issuer {
+            tracing::error!("discovery issuer {} - oidc", discovery.issuer);
+            return Err(login_failed());
+        }
+        Ok(discovery)
+    }
+
+    /// provider document at `url`, reused for `METADATA_TTL_SECONDS` unless `refresh` is set
+    async fn get<T: DeserializeOwned>(url: &str, refresh: bool) -> Result<T, AppError> {
+        static CACHE: OnceLock<Mutex<HashMap<String, (Instant, Value)>>> = OnceLock::new();
+        let cache = CACHE.get_or_init(Default::default);
+        let ttl = StdDuration::from_secs(METADATA_TTL_SECONDS);
+        let cached = cache
+            .lock()
+            .unwrap()
+            .get(url)
+            .filter(|(fetched_at, _)| !refresh && fetched_at.elapsed() < ttl)
+            .map(|(_, value)| value.clone());
+        let value = match cached {
+            Some(value) => value,
+            None => {
+                let value: Value = client()?
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(unavailable)?
+                    .json()
+                    .await
+                    .map_err(|e| {
+                        tracing::error!("{e} - oidc {url}");
+                        login_failed()
+                    })?;
+                cache
+                    .lock()
+                    .unwrap()
+                    .insert(url.to_string(), (Instant::now(), value.clone()));
+                value
+            }
+        };
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// url of the provider's login page, the state, nonce and PKCE verifier are kept until the callback.
+    pub async fn authorize_url(&self, db: &DBConnection) -> Result<String, AppError> {
+        let discovery = self.discover().await?;
+        let state = Token::generate();
+        let nonce = Token::generate();
+        let code_verifier = Token::generate();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+        sqlx::query!(
+            r#"
+                INSERT INTO oidc_login_requests (state, nonce, code_verifier, expires_at)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            state,
+            nonce,
+            code_verifier,
+            (Utc::now() + Duration::minutes(LOGIN_REQUEST_TTL_MINUTES)).naive_utc()
+        )
+        .execute(db)
+        .await?;
+        let url = Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", "openid email profile"),
+                ("state", &state),
+                ("nonce", &nonce),
+                ("code_challenge", &code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| {
+            AppError::MessageWithCause("Invalid authorization endpoint".into(), e.to_string())
+        })?;
+        Ok(url.to_string())
+    }
+
+    /// exchange the authorization code for an id token
+    async fn exchange(
+        &self,
+        discovery: &Discovery,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, AppError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response = client()?
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(unavailable)?;
+        if !response.status().is_success() {
+            let body = response.text().await.unwrap_or_default();
+            tracing::error!("{body} - oidc token exchange");
+            return Err(login_failed());
+        }
+        let body = response.text().await.unwrap_or_default();
+        let token: TokenResponse = serde_json::from_str(&body).map_err(|_| login_failed())?;
+        Ok(token.id_token)
+    }
+
+    /// verify the id token signature against the provider's JWKS, its issuer, audience, expiry and nonce.
+    async fn validate(
+        &self,
+        discovery: &Discovery,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let header = decode_header(id_token).map_err(|_| login_failed())?;
+        let find = |jwks: &JwkSet| match &header.kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            // without a kid, only a provider with a single key can tell which one signed it
+            None => match jwks.keys.as_slice() {
+                [jwk] => Some(jwk.clone()),
+                _ => None,
+            },
+        };
+        let jwks: JwkSet = Self::get(&discovery.jwks_uri, false).await?;
+        // an unknown key may have been rotated in since the JWKS was cached
+        let jwk = match find(&jwks) {
+            Some(jwk) => jwk,
+            None => find(&Self::get(&discovery.jwks_uri, true).await?).ok_or_else(login_failed)?,
+        };
+        // the algorithm comes from the provider's key, the token header only has to agree
+        let algorithm = jwk.common.algorithm.unwrap_or(header.alg);
+        if algorithm != header.alg || !ALLOWED_ALGORITHMS.contains(&algorithm) {
+            tracing::error!("{:?} - oidc id token algorithm", header.alg);
+            return Err(login_failed());
+        }
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| login_failed())?;
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| {
+                tracing::error!("{e} - oidc id token");
+                login_failed()
+            })?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(login_failed());
+        }
+        Ok(claims)
+    }
+
+    /// user linked to the identity, or the one with the provider's verified email and whether it's linked yet.
+    async fn find_user(
+        &self,
+        db: &DBConnection,
+        claims: &IdTokenClaims,
+    ) -> Result<(i32, bool), AppError> {
+        let user_id = sqlx::query_scalar!(
+            "SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2",
+            self.issuer,
+            claims.sub
+        )
+        .fetch_optional(db)
+        .await?;
+        if let Some(user_id) = user_id {
+            return Ok((user_id, true));
+        }
+        let no_account = || Response("oidc-no-account".into(), StatusCode::FORBIDDEN);
+        let (Some(email), Some(true)) = (&claims.email, claims.email_verified) else {
+            return Err(no_account());
+        };
+        let user_id =
+            sqlx::query_scalar!("SELECT id FROM users WHERE LOWER(email) = LOWER($1)", email)
+                .fetch_optional(db)
+                .await?
+                .ok_or_else(no_account)?;
+        Ok((user_id, false))
+    }
+
+    /// link the identity to the account, the provider verified its email
+    async fn link_user(
+        &self,
+        db: &DBConnection,
+        user_id: i32,
+        claims: &IdTokenClaims,
+    ) -> Result<(), AppError> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO user_identities (user_id, issuer, subject) VALUES ($1, $2, $3)",
+            user_id,
+            self.issuer,
+            claims.sub
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// query string the provider redirects back with
+#[derive(Serialize, Deserialize, Validate)]
+pub struct OidcCallback {
+    #[validate(length(min = 1, message = "oidc-code-required"))]
+    pub code: String,
+    #[validate(length(min = 1, message = "oidc-state-required"))]
+    pub state: String,
+}
+
+impl OidcCallback {
+    /// finish the authorization code flow and issue the normal login token
+    pub async fn login(
+        &self,
+        db: &DBConnection,
+        provider: &OidcProvider,
+    ) -> Result<String, AppError> {
+        // the state can only be used once
+        let request = sqlx::query!(
+            r#"
+                DELETE FROM oidc_login_requests
+                WHERE state = $1 AND expires_at > NOW()
+                RETURNING nonce, code_verifier
+            "#,
+            self.state
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| Response("oidc-request-invalid".into(), StatusCode::BAD_REQUEST))?;
+        let discovery = provider.discover().await?;
+        let id_token = provider
+            .exchange(&discovery, &self.code, &request.code_verifier)
+            .await?;
+        let claims = provider
+            .validate(&discovery, &id_token, &request.nonce)
+            .await?;
+        let (user_id, linked) = provider.find_user(db, &claims).await?;
+        // same checks as password login, before anything is changed on the account
+        let user = sqlx::query!(
+            r#"
+                SELECT
+                    status AS "status: UserStatus", email_verified_at,
+                    failed_login_count, last_failed_login_at, locked_until
+                FROM users
+                WHERE id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(db)
+        .await?;
+        if !user.status.is_active() {
+            return Err(Response("account-not-active".into(), StatusCode::FORBIDDEN));
+        }
+        LockoutPolicy::from_env().check_account(
+            user.failed_login_count,
+            user.last_failed_login_at,
+            user.locked_until,
+        )?;
+        if linked {
+            crate::verify_email::ensure_verified(user.email_verified_at)?;
+        } else {
+            provider.link_user(db, user_id, &claims).await?;
+        }
+        issue_token(db, user_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use http::StatusCode;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use reqwest::Url;
+    use serde_json::{json, Value};
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use services::db::DBConnection;
+    use services::encryption::Jwt;
+    use services::error::AppError;
+    use services::load_env;
+    use services::middleware::UserClaim;
+
+    use super::{Discovery, OidcCallback, OidcProvider};
+
+    const CLIENT_ID: &str = "crate-api";
+
+    /// mock provider with discovery and JWKS, the token endpoint is mounted per test
+    async fn mock_provider() -> (MockServer, OidcProvider) {
+        let server = MockServer::start().await;
+        let issuer = server.uri();
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{issuer}/authorize"),
+                "token_endpoint": format!("{issuer}/token"),
+                "jwks_uri": format!("{issuer}/jwks"),
+            })))
+            .mount(&server)
+            .await;
+        let jwks: Value =
+            serde_json::from_str(include_str!("fixtures/oidc-test-jwks.json")).unwrap();
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks))
+            .mount(&server)
+            .await;
+        let provider = OidcProvider {
+            issuer,
+            client_id: CLIENT_ID.into(),
+            client_secret: None,
+            redirect_uri: "http://localhost:8080/callback".into(),
+        };
+        (server, provider)
+    }
+
+    fn id_token(issuer: &str, nonce: &str, email: &str) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test-key".into());
+        let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/oidc-test-key.pem")).unwrap();
+        encode(&header, &claims(issuer, nonce, email), &key).unwrap()
+    }
+
+    fn claims(issuer: &str, nonce: &str, email: &str) -> Value {
+        json!({
+            "iss": issuer,
+            "aud": CLIENT_ID,
+            "sub": "external-42",
+            "email": email,
+            "email_verified": true,
+            "nonce": nonce,
+            "exp": (Utc::now() + Duration::minutes(5)).timestamp(),
+        })
+    }
+
+    /// start the flow and return the state and nonce sent to the provider
+    async fn authorize(pool: &DBConnection, provider: &OidcProvider) -> (String, String) {
+        let url = Url::parse(&provider.authorize_url(pool).await.unwrap()).unwrap();
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+                .unwrap()
+        };
+        assert_eq!(param("code_challenge_method"), "S256");
+        (param("state"), param("nonce"))
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_login_with_provider(pool: DBConnection) {
+        load_env(None);
+        let (server, provider) = mock_provider().await;
+        let (state, nonce) = authorize(&pool, &provider).await;
+        let email = sqlx::query_scalar!("SELECT email FROM users WHERE user_name = 'hubert'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("code_verifier="))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id_token": id_token(&provider.issuer, &nonce, &email),
+            })))
+            .mount(&server)
+            .await;
+        let callback = OidcCallback {
+            code: "auth-code".into(),
+            state: state.clone(),
+        };
+        let token = callback.login(&pool, &provider).await.unwrap();
+        let claim = Jwt::decode::<UserClaim>(&token).unwrap();
+        assert_eq!(claim.email, email);
+        let linked = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM user_identities WHERE subject = 'external-42'"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(linked, 1);
+
+        // the state can't be replayed
+        let result = callback.login(&pool, &provider).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::BAD_REQUEST))
+        ));
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_reject_wrong_nonce(pool: DBConnection) {
+        load_env(None);
+        let (server, provider) = mock_provider().await;
+        let (state, _) = authorize(&pool, &provider).await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id_token": id_token(&provider.issuer, "another-nonce", "sam@hgicrusade.com"),
+            })))
+            .mount(&server)
+            .await;
+        let callback = OidcCallback {
+            code: "auth-code".into(),
+            state,
+        };
+        let result = callback.login(&pool, &provider).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::UNAUTHORIZED))
+        ));
+    }
+
+    async fn callback(
+        pool: &DBConnection,
+        server: &MockServer,
+        provider: &OidcProvider,
+    ) -> OidcCallback {
+        let (state, nonce) = authorize(pool, provider).await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id_token": id_token(&provider.issuer, &nonce, "hubert@hgicrusade.com"),
+            })))
+            .mount(server)
+            .await;
+        OidcCallback {
+            code: "auth-code".into(),
+            state,
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_check_account_before_linking(pool: DBConnection) {
+        load_env(None);
+        let (server, provider) = mock_provider().await;
+        sqlx::query!("UPDATE users SET status = 'Inactive' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let result = callback(&pool, &server, &provider)
+            .await
+            .login(&pool, &provider)
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::FORBIDDEN))
+        ));
+        let user = sqlx::query!(
+            r#"
+                SELECT email_verified_at, EXISTS(SELECT 1 FROM user_identities) AS "linked!"
+                FROM users WHERE id = 1
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(user.email_verified_at.is_none());
+        assert!(!user.linked);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_reject_locked_accounts(pool: DBConnection) {
+        load_env(None);
+        let (server, provider) = mock_provider().await;
+        sqlx::query!("UPDATE users SET locked_until = NOW() + INTERVAL '5 minutes' WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let result = callback(&pool, &server, &provider)
+            .await
+            .login(&pool, &provider)
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::LOCKED))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn should_reject_provider_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/jwks-unavailable"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(json!({ "keys": [] })))
+            .mount(&server)
+            .await;
+        let url = format!("{}/jwks-unavailable", server.uri());
+        let result = OidcProvider::get::<Value>(&url, false).await;
+        assert!(matches!(result, Err(AppError::MessageWithCause(..))));
+    }
+
+    #[actix_web::test]
+    async fn should_cache_provider_documents() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/jwks-cached"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "keys": [] })))
+            .expect(2)
+            .mount(&server)
+            .await;
+        // mock servers are reused between tests, the path keeps the cached url unique
+        let url = format!("{}/jwks-cached", server.uri());
+        OidcProvider::get::<Value>(&url, false).await.unwrap();
+        OidcProvider::get::<Value>(&url, false).await.unwrap();
+        OidcProvider::get::<Value>(&url, true).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn should_pin_the_algorithm_and_key() {
+        let (server, provider) = mock_provider().await;
+        let mut jwks: Value =
+            serde_json::from_str(include_str!("fixtures/oidc-test-jwks.json")).unwrap();
+        let mut other = jwks["keys"][0].clone();
+        other["kid"] = "other-key".into();
+        jwks["keys"].as_array_mut().unwrap().push(other);
+        Mock::given(method("GET"))
+            .and(path("/jwks-two-keys"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks))
+            .mount(&server)
+            .await;
+        let discovery = |jwks: &str| Discovery {
+            issuer: provider.issuer.clone(),
+            authorization_endpoint: format!("{}/authorize", provider.issuer),
+            token_endpoint: format!("{}/token", provider.issuer),
+            jwks_uri: format!("{}/{jwks}", provider.issuer),
+        };
+        let claims = claims(&provider.issuer, "nonce", "hubert@hgicrusade.com");
+        let rsa_key =
+            EncodingKey::from_rsa_pem(include_bytes!("fixtures/oidc-test-key.pem")).unwrap();
+
+        // signed with the public key as an HMAC secret, the key only allows RS256
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("test-key".into());
+        let forged = encode(&header, &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+        let result = provider
+            .validate(&discovery("jwks"), &forged, "nonce")
+            .await;
+        assert!(result.is_err());
+
+        let unnamed = encode(&Header::new(Algorithm::RS256), &claims, &rsa_key).unwrap();
+        let result = provider
+            .validate(&discovery("jwks"), &unnamed, "nonce")
+            .await;
+        assert!(result.is_ok());
+        let result = provider
+            .validate(&discovery("jwks-two-keys"), &unnamed, "nonce")
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use bcrypt::{hash, verify};
+use chrono::{Duration, Utc};
+use http::StatusCode;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use strum_macros::AsRefStr;
+use validator::Validate as ActixValidator;
+
+use integration::twilio::Sms;
+use services::db::DBConnection;
+use services::error::AppError;
+use services::i18n::t_args;
+use AppError::Response;
+
+use crate::lockout::LockoutPolicy;
+use crate::login::issue_token;
+
+/// minutes before a sent code expires
+const CODE_TTL_MINUTES: i64 = 5;
+/// seconds to wait before another code can be requested
+const RESEND_COOLDOWN_SECONDS: i64 = 60;
+/// codes that can be requested per user and purpose in an hour
+const MAX_CODES_PER_HOUR: i64 = 5;
+/// wrong guesses allowed before a code is burned
+const MAX_ATTEMPTS: i32 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, AsRefStr)]
+pub enum OtpPurpose {
+    Login,
+    VerifyPhone,
+}
+
+pub struct OneTimePasscode;
+
+impl OneTimePasscode {
+    /// create a new code for the user, previous unused codes of the same purpose are expired.
+    /// returns the plain code so it can be sent to the user, only the hash is stored.
+    pub(crate) async fn issue(
+        db: &DBConnection,
+        user_id: i32,
+        purpose: OtpPurpose,
+    ) -> Result<String, AppError> {
+        let recent = sqlx::query!(
+            r#"
+                SELECT COUNT(*) AS "count!", MAX(created_at) AS last_sent_at
+                FROM one_time_passcodes
+                WHERE user_id = $1 AND purpose = $2 AND created_at > NOW() - INTERVAL '1 hour'
+            "#,
+            user_id,
+            purpose.as_ref()
+        )
+        .fetch_one(db)
+        .await?;
+        if let Some(last_sent_at) = recent.last_sent_at {
+            let wait =
+                last_sent_at + Duration::seconds(RESEND_COOLDOWN_SECONDS) - Utc::now().naive_utc();
+            if wait > Duration::zero() {
+                return Err(Response(
+                    t_args(
+                        "wait-before-new-code",
+                        &[("seconds", (wait.num_seconds() + 1).to_string())],
+                    ),
+                    StatusCode::TOO_MANY_REQUESTS,
+                ));
+            }
+        }
+        if recent.count >= MAX_CODES_PER_HOUR {
+            return Err(Response(
+                "too-many-codes".into(),
+                StatusCode::TOO_MANY_REQUESTS,
+            ));
+        }
+
+        let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        let hashed = hash(&code, 12).map_err(|e| AppError::Message(e.to_string()))?;
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            r#"
+                UPDATE one_time_passcodes SET expires_at = NOW()
+                WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+            "#,
+            user_id,
+            purpose.as_ref()
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+                INSERT INTO one_time_passcodes (user_id, purpose, code, expires_at)
+                VALUES ($1, $2, $3, NOW() + make_interval(mins => $4))
+            "#,
+            user_id,
+            purpose.as_ref(),
+            hashed,
+            CODE_TTL_MINUTES as i32
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(code)
+    }
+
+    /// mark the latest active code as used if it matches, every wrong guess is counted.
+    pub(crate) async fn consume(
+        db: &DBConnection,
+        user_id: i32,
+        purpose: OtpPurpose,
+        code: &str,
+    ) -> Result<(), AppError> {
+        let invalid = || Response("invalid-code".into(), StatusCode::BAD_REQUEST);
+        let mut tx = db.begin().await?;
+        let record = sqlx::query!(
+            r#"
+                SELECT id, code, attempts FROM one_time_passcodes
+                WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+                ORDER BY created_at DESC
+                LIMIT 1
+                FOR UPDATE
+            "#,
+            user_id,
+            purpose.as_ref()
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(invalid)?;
+        if record.attempts >= MAX_ATTEMPTS {
+            return Err(invalid());
+        }
+        if !verify(code, &record.code).unwrap_or(false) {
+            sqlx::query!(
+                "UPDATE one_time_passcodes SET attempts = attempts + 1 WHERE id = $1",
+                record.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Err(invalid());
+        }
+        sqlx::query!(
+            "UPDATE one_time_passcodes SET used_at = NOW() WHERE id = $1",
+            record.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct SendOtp {
+    #[validate(phone)]
+    pub phone: String,
+    pub purpose: OtpPurpose,
+}
+
+impl SendOtp {
+    /// send a code by sms. unknown numbers, and unverified numbers asking for a login code,
+    /// get the same response so the endpoint can't be used to look up registered phones.
+    pub async fn attempt(&self, db: &DBConnection) -> Result<(), AppError> {
+        let user = sqlx::query!(
+            "SELECT id, phone_verified_at FROM users WHERE phone = $1",
+            self.phone
+        )
+        .fetch_optional(db)
+        .await?;
+        let Some(user) = user else {
+            return Ok(());
+        };
+        let eligible = match self.purpose {
+            OtpPurpose::Login => user.phone_verified_at.is_some(),
+            OtpPurpose::VerifyPhone => user.phone_verified_at.is_none(),
+        };
+        if !eligible {
+            return Ok(());
+        }
+        let code = OneTimePasscode::issue(db, user.id, self.purpose).await?;
+        let body =
+            format!("Your verification code is {code}. It expires in {CODE_TTL_MINUTES} minutes.");
+        Sms::send(&self.phone, &body).await
+    }
+}
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct VerifyPhone {
+    #[validate(phone)]
+    pub phone: String,
+    #[validate(length(equal = 6, message = "code-length"))]
+    pub code: String,
+}
+
+impl VerifyPhone {
+    pub async fn attempt(&self, db: &DBConnection) -> Result<(), AppError> {
+        let user_id = sqlx::query_scalar!("SELECT id FROM users WHERE phone = $1", self.phone)
+            .fetch_optional(db)
+            .await?
+            .ok_or_else(|| Response("invalid-code".into(), StatusCode::BAD_REQUEST))?;
+        OneTimePasscode::consume(db, user_id, OtpPurpose::VerifyPhone, &self.code).await?;
+        sqlx::query!(
+            "UPDATE users SET phone_verified_at = NOW() WHERE id = $1",
+            user_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct OtpLogin {
+    #[validate(phone)]
+    pub phone: String,
+    #[validate(length(equal = 6, message = "code-length"))]
+    pub code: String,
+}
+
+impl OtpLogin {
+    /// exchange a login code for the same token issued by password login
+    pub async fn login(&self, db: &DBConnection) -> Result<String, AppError> {
+        let user = sqlx::query!(
+            r#"
+                SELECT
+                    id, email_verified_at,
+                    failed_login_count, last_failed_login_at, locked_until
+                FROM users
+                WHERE phone = $1 AND phone_verified_at IS NOT NULL
+            "#,
+            self.phone
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| Response("invalid-code".into(), StatusCode::UNAUTHORIZED))?;
+        LockoutPolicy::from_env().check_account(
+            user.failed_login_count,
+            user.last_failed_login_at,
+            user.locked_until,
+        )?;
+        OneTimePasscode::consume(db, user.id, OtpPurpose::Login, &self.code)
+            .await
+            .map_err(|e| match e {
+                Response(message, StatusCode::BAD_REQUEST) => {
+                    Response(message, StatusCode::UNAUTHORIZED)
+                }
+                e => e,
+            })?;
+        crate::verify_email::ensure_verified(user.email_verified_at)?;
+        issue_token(db, user.id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use services::db::DBConnection;
+    use services::error::AppError;
+    use services::load_env;
+
+    use super::*;
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_login_with_code_once(pool: DBConnection) {
+        load_env(None);
+        let code = OneTimePasscode::issue(&pool, 1, OtpPurpose::Login)
+            .await
+            .unwrap();
+        let form = OtpLogin {
+            phone: "+17786866393".into(),
+            code,
+        };
+        assert!(form.login(&pool).await.is_ok());
+        // codes are single use
+        assert!(form.login(&pool).await.is_err());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_burn_code_after_max_attempts(pool: DBConnection) {
+        let code = OneTimePasscode::issue(&pool, 1, OtpPurpose::Login)
+            .await
+            .unwrap();
+        for _ in 0..MAX_ATTEMPTS {
+            let result = OneTimePasscode::consume(&pool, 1, OtpPurpose::Login, "------").await;
+            assert!(result.is_err());
+        }
+        let result = OneTimePasscode::consume(&pool, 1, OtpPurpose::Login, &code).await;
+        assert!(result.is_err());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_throttle_resend(pool: DBConnection) {
+        assert!(OneTimePasscode::issue(&pool, 1, OtpPurpose::VerifyPhone)
+            .await
+            .is_ok());
+        let result = OneTimePasscode::issue(&pool, 1, OtpPurpose::VerifyPhone).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::TOO_MANY_REQUESTS))
+        ));
+    }
+}
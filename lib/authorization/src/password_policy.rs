@@ -0,0 +1,171 @@
+use http::StatusCode;
+use sqlx::PgConnection;
+
+use services::db::DBConnection;
+use services::env_or;
+use services::error::AppError;
+use services::i18n::{t, t_args};
+use services::password::PasswordHasher;
+use AppError::Response;
+
+/// bundled list of the most common passwords, one per line in lower case
+const COMMON_PASSWORDS: &str = include_str!("common-passwords.txt");
+
+/// Rules every new password must follow, at registration, reset and change.
+/// configured by the `PASSWORD_*` env vars.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// number of previous passwords that can't be used again, 0 to allow reuse
+    pub history: i64,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            min_length: env_or("PASSWORD_MIN_LENGTH", 8),
+            max_length: env_or("PASSWORD_MAX_LENGTH", 64),
+            require_lowercase: env_or("PASSWORD_REQUIRE_LOWERCASE", true),
+            require_uppercase: env_or("PASSWORD_REQUIRE_UPPERCASE", true),
+            require_digit: env_or("PASSWORD_REQUIRE_DIGIT", true),
+            require_symbol: env_or("PASSWORD_REQUIRE_SYMBOL", false),
+            history: env_or("PASSWORD_HISTORY", 5),
+        }
+    }
+
+    /// length, character classes and the common passwords list, all the failed rules are reported at once.
+    pub fn check(&self, password: &str) -> Result<(), AppError> {
+        let length = password.chars().count();
+        if length < self.min_length || length > self.max_length {
+            return Err(Response(
+                t_args(
+                    "password-length",
+                    &[
+                        ("min", self.min_length.to_string()),
+                        ("max", self.max_length.to_string()),
+                    ],
+                ),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        let has = |test: fn(char) -> bool| password.chars().any(test);
+        let mut missing = vec![];
+        if self.require_lowercase && !has(char::is_lowercase) {
+            missing.push(t("password-lowercase"));
+        }
+        if self.require_uppercase && !has(char::is_uppercase) {
+            missing.push(t("password-uppercase"));
+        }
+        if self.require_digit && !has(|c| c.is_ascii_digit()) {
+            missing.push(t("password-digit"));
+        }
+        if self.require_symbol && !has(|c| !c.is_alphanumeric()) {
+            missing.push(t("password-symbol"));
+        }
+        if !missing.is_empty() {
+            return Err(Response(
+                t_args("password-must-contain", &[("rules", missing.join(", "))]),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        if Self::is_common(password) {
+            return Err(Response(
+                "password-too-common".into(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        Ok(())
+    }
+
+    fn is_common(password: &str) -> bool {
+        let password = password.to_lowercase();
+        COMMON_PASSWORDS.lines().any(|common| common == password)
+    }
+
+    /// reject a password matching one of the user's last `history` passwords
+    pub async fn check_reuse(
+        &self,
+        db: &DBConnection,
+        user_id: i32,
+        password: &str,
+    ) -> Result<(), AppError> {
+        if self.history <= 0 {
+            return Ok(());
+        }
+        let hashes = sqlx::query_scalar!(
+            r#"
+                SELECT password_hash FROM password_history
+                WHERE user_id = $1
+                ORDER BY created_at DESC, id DESC
+                LIMIT $2
+            "#,
+            user_id,
+            self.history
+        )
+        .fetch_all(db)
+        .await?;
+        let hasher = PasswordHasher::from_env()?;
+        if hashes.iter().any(|hash| hasher.verify(password, hash)) {
+            return Err(Response(
+                t_args("password-history", &[("count", self.history.to_string())]),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        Ok(())
+    }
+
+    /// add the new password hash to the user's history, in the transaction that sets it
+    pub async fn remember(
+        conn: &mut PgConnection,
+        user_id: i32,
+        password_hash: &str,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            "INSERT INTO password_history (user_id, password_hash) VALUES ($1, $2)",
+            user_id,
+            password_hash
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordPolicy;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 64,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+            history: 5,
+        }
+    }
+
+    #[test]
+    fn should_check_length_and_character_classes() {
+        assert!(policy().check("Short1").is_err());
+        assert!(policy().check(&"Long1".repeat(20)).is_err());
+        let result = policy().check("lowercase only");
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Password must contain an uppercase letter, a number"
+        );
+        assert!(policy().check("Correct Horse 9").is_ok());
+    }
+
+    #[test]
+    fn should_reject_common_passwords() {
+        assert!(policy().check("Password123").is_err());
+        assert!(policy().check("P@ssw0rd").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use validator::Validate as ActixValidator;
+
+use services::db::DBConnection;
+use services::error::AppError;
+use services::i18n::Locale;
+use services::middleware::UserClaim;
+use services::password::PasswordHasher;
+use services::session::Session;
+use services::users::{State, User};
+use services::Country;
+use AppError::Response;
+
+use crate::lockout::LockoutPolicy;
+use crate::otp::{OtpPurpose, SendOtp};
+use crate::password_policy::PasswordPolicy;
+
+/// Account of the logged-in user, `/me`
+pub type Profile = User;
+
+/// fields left out are not changed
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct UpdateProfile {
+    #[validate(length(min = 1, max = 255, message = "first-name-length"))]
+    pub first_name: Option<String>,
+    #[validate(length(min = 1, max = 255, message = "last-name-length"))]
+    pub last_name: Option<String>,
+    #[validate(phone)]
+    pub phone: Option<String>,
+    #[validate(url, length(max = 255))]
+    pub photo: Option<String>,
+    pub state: Option<State>,
+    pub country: Option<Country>,
+    /// used from the next login on
+    pub locale: Option<Locale>,
+}
+
+impl UpdateProfile {
+    /// a new phone number has to be verified again, the code is sent right away
+    pub async fn attempt(&self, db: &DBConnection, user_id: i32) -> Result<Profile, AppError> {
+        let current = Profile::find(db, user_id).await?;
+        let phone = self.phone.as_ref().filter(|phone| **phone != current.phone);
+        if let Some(phone) = phone {
+            let taken = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM users WHERE phone = $1 AND id <> $2) AS "taken!""#,
+                phone,
+                user_id
+            )
+            .fetch_one(db)
+            .await?;
+            if taken {
+                return Err(Response("phone-taken".into(), StatusCode::CONFLICT));
+            }
+        }
+        sqlx::query!(
+            r#"
+                UPDATE users SET
+                    first_name = COALESCE($1, first_name),
+                    last_name = COALESCE($2, last_name),
+                    phone = COALESCE($3, phone),
+                    phone_verified_at = CASE WHEN $3::VARCHAR IS NULL THEN phone_verified_at END,
+                    photo = COALESCE($4, photo),
+                    state = COALESCE($5, state),
+                    country = COALESCE($6, country),
+                    locale = COALESCE($7, locale)
+                WHERE id = $8
+            "#,
+            self.first_name,
+            self.last_name,
+            phone,
+            self.photo,
+            self.state.as_ref().map(AsRef::<str>::as_ref),
+            self.country.as_ref().map(AsRef::<str>::as_ref),
+            self.locale.as_ref().map(AsRef::<str>::as_ref),
+            user_id
+        )
+        .execute(db)
+        .await?;
+        if let Some(phone) = phone {
+            let send_code = SendOtp {
+                phone: phone.clone(),
+                purpose: OtpPurpose::VerifyPhone,
+            };
+            if let Err(e) = send_code.attempt(db).await {
+                tracing::error!("{e} - verification sms {user_id}");
+            }
+        }
+        Profile::find(db, user_id).await
+    }
+}
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct ChangePassword {
+    pub current_password: String,
+    /// checked against `PasswordPolicy`
+    pub new_password: String,
+    #[validate(must_match(other = "new_password", message = "passwords-mismatch"))]
+    pub re_type_password: String,
+}
+
+impl ChangePassword {
+    /// every other session of the user is revoked, the current one stays logged in.
+    /// wrong current passwords count as failed logins, so a stolen session can't guess it.
+    pub async fn attempt(
+        &self,
+        db: &DBConnection,
+        user: &UserClaim,
+        ip: Option<&str>,
+    ) -> Result<(), AppError> {
+        let stored = sqlx::query!(
+            r#"
+                SELECT user_name, password, failed_login_count, last_failed_login_at, locked_until
+                FROM users
+                WHERE id = $1
+            "#,
+            user.id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User".into()))?;
+        let lockout = LockoutPolicy::from_env();
+        lockout.check_account(
+            stored.failed_login_count,
+            stored.last_failed_login_at,
+            stored.locked_until,
+        )?;
+        let hasher = PasswordHasher::from_env()?;
+        if !hasher.verify(&self.current_password, &stored.password) {
+            if lockout.record_failure(db, &stored.user_name, ip).await? {
+                return Err(Response("account-locked".into(), StatusCode::LOCKED));
+            }
+            return Err(Response(
+                "current-password-incorrect".into(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        lockout.record_success(db, &stored.user_name, ip).await?;
+        let policy = PasswordPolicy::from_env();
+        policy.check(&self.new_password)?;
+        policy.check_reuse(db, user.id, &self.new_password).await?;
+        let password = hasher.hash(&self.new_password)?;
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "UPDATE users SET password = $1 WHERE id = $2",
+            password,
+            user.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        PasswordPolicy::remember(&mut tx, user.id, &password).await?;
+        tx.commit().await?;
+        Session::revoke_all(db, user.id, Some(user.sid)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use services::db::DBConnection;
+    use services::error::AppError;
+    use services::i18n::Locale;
+    use services::load_env;
+    use services::middleware::UserClaim;
+    use services::session::Session;
+    use services::users::{State, UserType};
+    use services::Country;
+
+    use super::{ChangePassword, Profile, UpdateProfile};
+
+    async fn claim(pool: &DBConnection) -> UserClaim {
+        let sid = Session::create(pool, 1, UserClaim::expires_at())
+            .await
+            .unwrap();
+        UserClaim::new(
+            1,
+            sid,
+            UserType::Associate,
+            "Hubert".into(),
+            "Humphrey".into(),
+            "hubert@hgicrusade.com".into(),
+            None,
+        )
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_update_only_given_fields(pool: DBConnection) {
+        let form = UpdateProfile {
+            first_name: Some("Bert".into()),
+            last_name: None,
+            phone: None,
+            photo: None,
+            state: Some(State::BC),
+            country: Some(Country::CA),
+            locale: Some(Locale::Fr),
+        };
+        let profile = form.attempt(&pool, 1).await.unwrap();
+        assert_eq!(profile.first_name, "Bert");
+        assert_eq!(profile.last_name, "Humphrey");
+        assert_eq!(profile.state, State::BC);
+        assert_eq!(profile.locale, Some(Locale::Fr));
+        // the phone didn't change so it stays verified
+        assert!(profile.phone_verified_at.is_some());
+        assert_eq!(Profile::find(&pool, 1).await.unwrap().country, Country::CA);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_change_password_and_keep_current_session(pool: DBConnection) {
+        load_env(None);
+        let user = claim(&pool).await;
+        let other = Session::create(&pool, 1, UserClaim::expires_at())
+            .await
+            .unwrap();
+        let form = |current: &str| ChangePassword {
+            current_password: current.into(),
+            new_password: "New-password1".into(),
+            re_type_password: "New-password1".into(),
+        };
+        let result = form("wrong-password").attempt(&pool, &user, None).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::BAD_REQUEST))
+        ));
+        form("password123")
+            .attempt(&pool, &user, None)
+            .await
+            .unwrap();
+        assert!(Session::is_active(&pool, user.sid).await.unwrap());
+        assert!(!Session::is_active(&pool, other).await.unwrap());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_lock_after_wrong_current_passwords(pool: DBConnection) {
+        load_env(None);
+        let user = claim(&pool).await;
+        let form = |current: &str| ChangePassword {
+            current_password: current.into(),
+            new_password: "New-password1".into(),
+            re_type_password: "New-password1".into(),
+        };
+        // one failure short of the lock
+        sqlx::query!(
+            "UPDATE users SET failed_login_count = 4, last_failed_login_at = NULL WHERE id = 1"
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let result = form("wrong-password")
+            .attempt(&pool, &user, Some("10.0.0.1"))
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::LOCKED))
+        ));
+        // the right password doesn't help while locked
+        let result = form("password123").attempt(&pool, &user, None).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::LOCKED))
+        ));
+    }
+}
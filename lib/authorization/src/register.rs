@@ -1,32 +1,51 @@
-use chrono::NaiveDate;
+use std::env::var;
+
 use http::StatusCode;
-use rand::Rng;
 use serde::{Deserialize, Serialize};
-use sqlx::Type;
-use validator::Validate as ActixValidator;
+use serde_json::json;
+use validator::{Validate as ActixValidator, ValidationError};
 
+use integration::sqs::Sqs;
+use integration::stripe::Payment;
 use services::db::DBConnection;
 use services::error::AppError;
-use services::users::State;
+use services::password::PasswordHasher;
+use services::queue::{Message, MessageType};
+use services::reporting::breadcrumb;
+use services::users::{State, UserStatus, UserType};
 use services::Country;
 use AppError::Response;
 
+use crate::otp::{OtpPurpose, SendOtp};
+use crate::password_policy::PasswordPolicy;
+use crate::verify_email::EmailVerification;
+
 #[derive(Serialize, ActixValidator, Deserialize, Clone)]
 pub struct CreditCardInfo {
+    #[validate(custom = "card_token")]
     pub token: String,
-    pub last4: i32,
+    /// a string, cards can end with zeros like `0042`
+    #[validate(custom = "card_last4")]
+    pub last4: String,
 }
 
-impl CreditCardInfo {
-    pub fn validate(&self) -> Result<(), AppError> {
-        if !self.token.starts_with("tok_") || self.last4.to_string().len() != 4 {
-            return Err(Response(
-                "Invalid Payment Information".to_string(),
-                StatusCode::BAD_REQUEST,
-            ));
-        }
-        Ok(())
+/// tokens come from Stripe.js, card numbers are never sent to the api
+fn card_token(token: &str) -> Result<(), ValidationError> {
+    if token.starts_with("tok_") {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("card_token");
+    error.message = Some("card-token-invalid".into());
+    Err(error)
+}
+
+fn card_last4(last4: &str) -> Result<(), ValidationError> {
+    if last4.len() == 4 && last4.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(());
     }
+    let mut error = ValidationError::new("card_last4");
+    error.message = Some("card-last4-invalid".into());
+    Err(error)
 }
 
 #[derive(Serialize, ActixValidator, Deserialize, Clone)]
@@ -41,21 +60,319 @@ pub struct RegistrationForm {
     pub state: State,
     pub country: Country,
     pub password: String,
+    #[validate]
     pub card: Option<CreditCardInfo>,
 }
 
-#[allow(unused)]
+/// what a registration with a card is charged, and the Stripe accounts charging it
+pub struct RegistrationFee {
+    pub cents: i64,
+    pub payment: Payment,
+}
+
+impl RegistrationFee {
+    /// `REGISTRATION_FEE_CENTS`, e.g. `19999` for 199.99
+    pub fn from_env() -> Result<Self, AppError> {
+        let cents = var("REGISTRATION_FEE_CENTS")?
+            .parse::<i64>()
+            .map_err(|_| AppError::Message("Invalid registration fee".into()))?;
+        Ok(Self {
+            cents,
+            payment: Payment::from_env()?,
+        })
+    }
+}
+
 impl RegistrationForm {
-    /// register new user after validating unique fields, check if coupon was applied,
-    /// charge payment through stripe, Add to queue for RV points and hierarchy snapshot records.
-    pub async fn register(&self, db: &DBConnection) -> Result<(), AppError> {
-        if let Some(card) = &self.card {
-            card.validate()?;
+    /// register new user after validating unique fields, charge payment through stripe,
+    /// Add to queue for RV points and hierarchy snapshot records.
+    pub async fn register(&self, db: &DBConnection) -> Result<i32, AppError> {
+        let fee = match self.card {
+            Some(_) => Some(RegistrationFee::from_env()?),
+            None => None,
+        };
+        self.register_with(db, fee.as_ref()).await
+    }
+
+    /// the card is charged before the user is committed, a declined card rolls the user back.
+    pub async fn register_with(
+        &self,
+        db: &DBConnection,
+        fee: Option<&RegistrationFee>,
+    ) -> Result<i32, AppError> {
+        self.validate()?;
+        PasswordPolicy::from_env().check(&self.password)?;
+        ensure_unique(db, &self.user_name, &self.email, &self.phone, None).await?;
+        let password = PasswordHasher::from_env()?.hash(&self.password)?;
+
+        let mut tx = db.begin().await?;
+        let user_id = sqlx::query_scalar!(
+            r#"
+                INSERT INTO users (first_name, last_name, user_name, email, password, phone, type, state, country, status)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                RETURNING id
+            "#,
+            self.first_name,
+            self.last_name,
+            self.user_name,
+            self.email,
+            password,
+            self.phone,
+            UserType::Associate.as_ref(),
+            self.state.as_ref(),
+            self.country.as_ref(),
+            UserStatus::Active.as_ref()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        PasswordPolicy::remember(&mut tx, user_id, &password).await?;
+        let charge_id = match &self.card {
+            Some(card) => {
+                let fee = fee.ok_or_else(|| {
+                    AppError::Message("Registration fee is not configured".into())
+                })?;
+                Some(self.charge(fee, card).await?)
+            }
+            None => None,
+        };
+        if let Err(e) = tx.commit().await {
+            // the card token is the idempotency key, registering again with it doesn't charge twice
+            if let Some(charge_id) = &charge_id {
+                tracing::error!("{e} - charge {charge_id} without a registration");
+            }
+            return Err(e.into());
+        }
+        if let Some(charge_id) = &charge_id {
+            breadcrumb("payment", format!("card charged {charge_id}"));
+        }
+        breadcrumb("register", format!("user {user_id} created"));
+
+        if let Err(e) = Self::enqueue(user_id, charge_id).await {
+            tracing::error!("{e} - registration {user_id}");
         }
-        // do magic!
-        Ok(())
+        if let Err(e) = EmailVerification::send(db, user_id).await {
+            tracing::error!("{e} - verification email {user_id}");
+        }
+        let send_code = SendOtp {
+            phone: self.phone.clone(),
+            purpose: OtpPurpose::VerifyPhone,
+        };
+        if let Err(e) = send_code.attempt(db).await {
+            tracing::error!("{e} - verification sms {user_id}");
+        }
+        Ok(user_id)
+    }
+
+    async fn charge(
+        &self,
+        fee: &RegistrationFee,
+        card: &CreditCardInfo,
+    ) -> Result<String, AppError> {
+        fee.payment
+            .charge(
+                &self.first_name,
+                &self.last_name,
+                &self.email,
+                fee.cents,
+                &self.country,
+                &card.token,
+            )
+            .await
+    }
+
+    async fn enqueue(user_id: i32, charge_id: Option<String>) -> Result<(), AppError> {
+        let queue_url = var("JOBS_QUEUE_URL")?;
+        let message = Message::new(
+            MessageType::Registration,
+            json!({ "user_id": user_id, "charge_id": charge_id }).to_string(),
+        );
+        Sqs::new(queue_url)
+            .await
+            .add(message)
+            .await
+            .map_err(|e| AppError::Message(e.to_string()))
     }
 }
 
+/// 409 when another user, other than `except`, already has the user name, email or phone
+pub async fn ensure_unique(
+    db: &DBConnection,
+    user_name: &str,
+    email: &str,
+    phone: &str,
+    except: Option<i32>,
+) -> Result<(), AppError> {
+    let existing = sqlx::query!(
+        r#"
+            SELECT
+                user_name = $1 AS "user_name!", email = $2 AS "email!", phone = $3 AS "phone!"
+            FROM users
+            WHERE (user_name = $1 OR email = $2 OR phone = $3) AND id IS DISTINCT FROM $4
+        "#,
+        user_name,
+        email,
+        phone,
+        except
+    )
+    .fetch_all(db)
+    .await?;
+    let message = if existing.iter().any(|u| u.user_name) {
+        "user-name-taken"
+    } else if existing.iter().any(|u| u.email) {
+        "email-taken"
+    } else if existing.iter().any(|u| u.phone) {
+        "phone-taken"
+    } else {
+        return Ok(());
+    };
+    Err(Response(message.into(), StatusCode::CONFLICT))
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use http::StatusCode;
+
+    use services::db::DBConnection;
+    use services::error::AppError;
+    use services::users::State;
+    use services::Country;
+    use validator::Validate;
+
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use integration::stripe::Payment;
+
+    use super::{CreditCardInfo, RegistrationFee, RegistrationForm};
+
+    fn form() -> RegistrationForm {
+        RegistrationForm {
+            first_name: "Sam".into(),
+            last_name: "Rusty".into(),
+            email: "sam@hgicrusade.com".into(),
+            phone: "+16045550100".into(),
+            user_name: "sam".into(),
+            state: State::BC,
+            country: Country::CA,
+            password: "Password-123".into(),
+            card: None,
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_reject_taken_fields(pool: DBConnection) {
+        let taken = [
+            RegistrationForm {
+                user_name: "hubert".into(),
+                ..form()
+            },
+            RegistrationForm {
+                email: "hubert@hgicrusade.com".into(),
+                ..form()
+            },
+            RegistrationForm {
+                phone: "+17786866393".into(),
+                ..form()
+            },
+        ];
+        for registration in taken {
+            let result = registration.register(&pool).await;
+            assert!(matches!(
+                result,
+                Err(AppError::Response(_, StatusCode::CONFLICT))
+            ));
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_report_invalid_card_fields(pool: DBConnection) {
+        let registration = RegistrationForm {
+            card: Some(CreditCardInfo {
+                token: "4242424242424242".into(),
+                last4: "42".into(),
+            }),
+            ..form()
+        };
+        let Err(AppError::Validation(fields)) = registration.register_with(&pool, None).await
+        else {
+            panic!("card should be invalid");
+        };
+        assert_eq!(fields["card.token"][0].code, "card_token");
+        assert_eq!(fields["card.last4"][0].code, "card_last4");
+    }
+
+    #[test]
+    fn should_accept_last4_with_leading_zeros() {
+        let card = |last4: &str| CreditCardInfo {
+            token: "tok_visa".into(),
+            last4: last4.into(),
+        };
+        assert!(card("0042").validate().is_ok());
+        assert!(card("4242").validate().is_ok());
+        assert!(card("42").validate().is_err());
+        assert!(card("42a2").validate().is_err());
+        assert!(card("42424").validate().is_err());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_only_keep_paid_registrations(pool: DBConnection) {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/charges"))
+            .and(body_string_contains("tok_chargeDeclined"))
+            .respond_with(ResponseTemplate::new(402).set_body_json(
+                serde_json::json!({ "error": { "message": "Your card was declined." } }),
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/charges"))
+            .and(header("Idempotency-Key", "tok_visa"))
+            .and(body_string_contains("amount=19999"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "id": "ch_1", "status": "succeeded" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let fee = RegistrationFee {
+            cents: 19999,
+            payment: Payment {
+                api_url: server.uri(),
+                secret_key_ca: "Bearer sk_test".into(),
+                secret_key_us: "Bearer sk_test".into(),
+            },
+        };
+        let paying = |token: &str| RegistrationForm {
+            card: Some(CreditCardInfo {
+                token: token.into(),
+                last4: "4242".into(),
+            }),
+            ..form()
+        };
+
+        let declined = paying("tok_chargeDeclined")
+            .register_with(&pool, Some(&fee))
+            .await;
+        assert!(matches!(
+            declined,
+            Err(AppError::Response(_, StatusCode::BAD_REQUEST))
+        ));
+        let saved = sqlx::query_scalar!("SELECT id FROM users WHERE user_name = 'sam'")
+            .fetch_optional(&pool)
+            .await
+            .unwrap();
+        assert!(saved.is_none());
+
+        let id = paying("tok_visa")
+            .register_with(&pool, Some(&fee))
+            .await
+            .unwrap();
+        let status = sqlx::query_scalar!("SELECT status FROM users WHERE id = $1", id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "Active");
+    }
+}
@@ -1,52 +1,123 @@
-use bcrypt::hash;
 use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use validator::Validate as ActixValidator;
 
 use services::db::DBConnection;
 use services::error::AppError;
+use services::password::PasswordHasher;
+use services::session::Session;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ResetPasswordPayload {
-    pub id: i32,
-    pub user_name: String,
-}
+use crate::password_policy::PasswordPolicy;
+use crate::single_use_token::{SingleUseToken, TokenPurpose};
 
 #[derive(Serialize, ActixValidator, Deserialize)]
 pub struct ResetPassword {
     pub token: String,
-    #[validate(length(
-        min = 8,
-        max = 64,
-        message = "Password must be between 8 and 64 characters"
-    ))]
+    /// checked against `PasswordPolicy`
     pub new_password: String,
-    #[validate(must_match(other = "new_password", message = "Passwords do not match"))]
+    #[validate(must_match(other = "new_password", message = "passwords-mismatch"))]
     pub re_type_password: String,
 }
 
 impl ResetPassword {
+    /// the token can only be used once, other reset links and all sessions of the user are revoked.
     pub async fn attempt(&self, db: &DBConnection) -> Result<(), AppError> {
-        let payload = services::encryption::Jwt::decode::<ResetPasswordPayload>(&self.token)?;
-        let password =
-            hash(&self.new_password, 12).map_err(|e| AppError::Message(e.to_string()))?;
-        let rows_affected = sqlx::query!(
-            "UPDATE users SET password = $1 WHERE user_name = $2",
+        let policy = PasswordPolicy::from_env();
+        policy.check(&self.new_password)?;
+        let password = PasswordHasher::from_env()?.hash(&self.new_password)?;
+        let mut tx = db.begin().await?;
+        let user_id = SingleUseToken::consume(&mut *tx, TokenPurpose::PasswordReset, &self.token)
+            .await?
+            .ok_or_else(|| AppError::Response("invalid-token".into(), StatusCode::BAD_REQUEST))?;
+        // the token stays unused when the password is rejected
+        policy.check_reuse(db, user_id, &self.new_password).await?;
+        sqlx::query!(
+            // a successful reset also unlocks the account
+            r#"
+                UPDATE users SET password = $1, failed_login_count = 0, locked_until = NULL
+                WHERE id = $2
+            "#,
             password,
-            payload.user_name
+            user_id
         )
-        .execute(db)
-        .await?
-        .rows_affected();
-        if rows_affected == 1 {
-            return Ok(());
-        }
-        Err(AppError::Response(
-            "Invalid Token".into(),
-            StatusCode::BAD_REQUEST,
-        ))
+        .execute(&mut *tx)
+        .await?;
+        PasswordPolicy::remember(&mut tx, user_id, &password).await?;
+        SingleUseToken::expire(&mut tx, user_id, TokenPurpose::PasswordReset).await?;
+        tx.commit().await?;
+        Session::revoke_all(db, user_id, None).await
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use services::db::DBConnection;
+    use services::session::Session;
+
+    use crate::single_use_token::{SingleUseToken, TokenPurpose};
+
+    use super::ResetPassword;
+
+    fn form(link: &str) -> ResetPassword {
+        ResetPassword {
+            token: link.rsplit('/').next().unwrap().to_string(),
+            new_password: "New-password1".into(),
+            re_type_password: "New-password1".into(),
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_use_token_once_and_revoke_sessions(pool: DBConnection) {
+        let expires_at = services::middleware::UserClaim::expires_at();
+        let session = Session::create(&pool, 1, expires_at).await.unwrap();
+        let reset = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::PasswordReset)
+                .await
+                .unwrap(),
+        );
+        assert!(reset.attempt(&pool).await.is_ok());
+        assert!(reset.attempt(&pool).await.is_err());
+        assert!(!Session::is_active(&pool, session).await.unwrap());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_revoke_older_links(pool: DBConnection) {
+        let first = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::PasswordReset)
+                .await
+                .unwrap(),
+        );
+        let second = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::PasswordReset)
+                .await
+                .unwrap(),
+        );
+        assert!(first.attempt(&pool).await.is_err());
+        assert!(second.attempt(&pool).await.is_ok());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_not_reuse_recent_passwords(pool: DBConnection) {
+        assert!(form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::PasswordReset)
+                .await
+                .unwrap()
+        )
+        .attempt(&pool)
+        .await
+        .is_ok());
+        let reset = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::PasswordReset)
+                .await
+                .unwrap(),
+        );
+        assert!(reset.attempt(&pool).await.is_err());
+        // the link can still be used with another password
+        let reset = ResetPassword {
+            new_password: "Other-password2".into(),
+            re_type_password: "Other-password2".into(),
+            ..reset
+        };
+        assert!(reset.attempt(&pool).await.is_ok());
+    }
+}
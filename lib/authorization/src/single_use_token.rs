@@ -0,0 +1,160 @@
+use std::env::var;
+
+use sqlx::{PgConnection, PgExecutor};
+use strum_macros::AsRefStr;
+
+use services::db::DBConnection;
+use services::encryption::Token;
+use services::env_or;
+use services::error::AppError;
+
+/// minutes before a reset link expires
+const RESET_TOKEN_TTL_MINUTES: i32 = 10;
+/// minutes before a magic link expires, `MAGIC_LINK_TTL_MINUTES`
+const DEFAULT_MAGIC_LINK_TTL_MINUTES: i32 = 15;
+/// minutes before a verification link expires
+const VERIFICATION_TOKEN_TTL_MINUTES: i32 = 24 * 60;
+
+/// what a link emailed to the user does, each one opens its own page of the site
+#[derive(Debug, Clone, Copy, PartialEq, AsRefStr)]
+pub enum TokenPurpose {
+    PasswordReset,
+    MagicLink,
+    EmailVerification,
+}
+
+impl TokenPurpose {
+    fn path(&self) -> &'static str {
+        match self {
+            Self::PasswordReset => "reset-password",
+            Self::MagicLink => "magic-link",
+            Self::EmailVerification => "verify-email",
+        }
+    }
+
+    fn ttl_minutes(&self) -> i32 {
+        match self {
+            Self::PasswordReset => RESET_TOKEN_TTL_MINUTES,
+            Self::MagicLink => env_or("MAGIC_LINK_TTL_MINUTES", DEFAULT_MAGIC_LINK_TTL_MINUTES),
+            Self::EmailVerification => VERIFICATION_TOKEN_TTL_MINUTES,
+        }
+    }
+}
+
+pub struct SingleUseToken;
+
+impl SingleUseToken {
+    /// link to the page of the purpose with a new token.
+    /// only the hash of the token is stored and older unused links of the same purpose stop working.
+    pub(crate) async fn link(
+        db: &DBConnection,
+        user_id: i32,
+        purpose: TokenPurpose,
+    ) -> Result<String, AppError> {
+        let token = Token::generate();
+        let mut tx = db.begin().await?;
+        Self::expire(&mut tx, user_id, purpose).await?;
+        sqlx::query!(
+            r#"
+                INSERT INTO single_use_tokens (user_id, purpose, token_hash, expires_at)
+                VALUES ($1, $2, $3, NOW() + make_interval(mins => $4))
+            "#,
+            user_id,
+            purpose.as_ref(),
+            Token::hash(&token),
+            purpose.ttl_minutes()
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        let url = var("SITE_URL").unwrap_or("http://domain.com/".into());
+        Ok(format!("{url}{}/{token}", purpose.path()))
+    }
+
+    /// user of the token, which is marked as used. `None` when it's unknown, used or expired.
+    /// run it in a transaction to keep the token when the action fails afterwards.
+    pub(crate) async fn consume<'c>(
+        conn: impl PgExecutor<'c>,
+        purpose: TokenPurpose,
+        token: &str,
+    ) -> Result<Option<i32>, AppError> {
+        let user_id = sqlx::query_scalar!(
+            r#"
+                UPDATE single_use_tokens SET used_at = NOW()
+                WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+                RETURNING user_id
+            "#,
+            Token::hash(token),
+            purpose.as_ref()
+        )
+        .fetch_optional(conn)
+        .await?;
+        Ok(user_id)
+    }
+
+    /// unused links of the user for the purpose stop working
+    pub(crate) async fn expire(
+        conn: &mut PgConnection,
+        user_id: i32,
+        purpose: TokenPurpose,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                UPDATE single_use_tokens SET expires_at = NOW()
+                WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+            "#,
+            user_id,
+            purpose.as_ref()
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use services::db::DBConnection;
+
+    use super::{SingleUseToken, TokenPurpose};
+
+    fn token(link: &str) -> &str {
+        link.rsplit('/').next().unwrap()
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_consume_latest_token_of_purpose_once(pool: DBConnection) {
+        let first = SingleUseToken::link(&pool, 1, TokenPurpose::MagicLink)
+            .await
+            .unwrap();
+        let second = SingleUseToken::link(&pool, 1, TokenPurpose::MagicLink)
+            .await
+            .unwrap();
+        let reset = SingleUseToken::link(&pool, 1, TokenPurpose::PasswordReset)
+            .await
+            .unwrap();
+        assert!(second.contains("/magic-link/"));
+        let consume = |link, purpose| SingleUseToken::consume(&pool, purpose, token(link));
+        assert_eq!(
+            consume(&first, TokenPurpose::MagicLink).await.unwrap(),
+            None
+        );
+        // a link can't be used for another purpose
+        assert_eq!(
+            consume(&reset, TokenPurpose::MagicLink).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            consume(&second, TokenPurpose::MagicLink).await.unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            consume(&second, TokenPurpose::MagicLink).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            consume(&reset, TokenPurpose::PasswordReset).await.unwrap(),
+            Some(1)
+        );
+    }
+}
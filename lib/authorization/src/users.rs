@@ -0,0 +1,534 @@
+use std::fmt::Debug;
+
+use http::StatusCode;
+use scooby::postgres::select;
+use serde::{Deserialize, Serialize};
+use struct_iterable::Iterable;
+use validator::Validate;
+
+use services::db::DBConnection;
+use services::error::AppError;
+use services::guard::AccountHolder;
+use services::i18n::t_args;
+use services::middleware::UserClaim;
+use services::password::PasswordHasher;
+use services::query_param::{EnumFilter, OrderBy, Page, QueryParams, StringFilter};
+use services::session::Session;
+use services::users::{State, UserStatus, UserType};
+use services::Country;
+use AppError::Response;
+
+use crate::otp::{OtpPurpose, SendOtp};
+use crate::password_policy::PasswordPolicy;
+use crate::profile::Profile;
+use crate::register::ensure_unique;
+use crate::verify_email::EmailVerification;
+
+#[derive(Deserialize, Serialize, Debug, Iterable)]
+pub struct FilterColumns {
+    pub first_name: StringFilter,
+    pub last_name: StringFilter,
+    pub user_name: StringFilter,
+    pub email: StringFilter,
+    pub user_type: EnumFilter<UserType>,
+    pub status: EnumFilter<UserStatus>,
+    pub state: EnumFilter<State>,
+    pub country: EnumFilter<Country>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Iterable)]
+pub struct OrderColumns {
+    pub id: OrderBy,
+    pub first_name: OrderBy,
+    pub last_name: OrderBy,
+    pub user_name: OrderBy,
+    pub created_at: OrderBy,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, PartialEq, Debug)]
+pub struct UserList {
+    pub id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub user_name: String,
+    pub email: String,
+    pub user_type: String,
+    pub status: String,
+    pub state: String,
+    pub country: String,
+}
+
+impl UserList {
+    pub async fn find(
+        db: &DBConnection,
+        params: QueryParams<FilterColumns, OrderColumns>,
+    ) -> Result<Page<Self>, AppError> {
+        // `type` is a keyword in rust, it's filtered as `user_type`
+        let query = select(
+            "id, first_name, last_name, user_name, email, user_type, status, state, country",
+        )
+        .from("(SELECT *, type AS user_type FROM users) users");
+        let (query, args, _, limit) = params.build_query(query, "", 20)?;
+        let sql = query.to_string();
+        let result: Vec<Self> = sqlx::query_as_with(&sql, args).fetch_all(db).await?;
+        Ok(params.page(result, limit))
+    }
+}
+
+/// user added by an admin, no payment is taken
+#[derive(Serialize, Validate, Deserialize)]
+pub struct NewUser {
+    #[validate(length(min = 1, max = 255, message = "first-name-length"))]
+    pub first_name: String,
+    #[validate(length(min = 1, max = 255, message = "last-name-length"))]
+    pub last_name: String,
+    #[validate(length(min = 1, max = 255, message = "user-name-length"))]
+    pub user_name: String,
+    #[validate(email)]
+    pub email: String,
+    #[validate(phone)]
+    pub phone: String,
+    pub user_type: UserType,
+    pub state: State,
+    pub country: Country,
+    /// checked against `PasswordPolicy`
+    pub password: String,
+}
+
+/// only an admin signed in as themselves gives or removes the Admin type.
+/// `holder` is `None` for API keys and impersonated sessions, see `AccountHolder`.
+fn ensure_admin_holder(holder: Option<&AccountHolder>) -> Result<(), AppError> {
+    match holder {
+        Some(AccountHolder(user)) if user.is_admin() => Ok(()),
+        _ => Err(Response(
+            "admin-type-not-allowed".into(),
+            StatusCode::FORBIDDEN,
+        )),
+    }
+}
+
+impl NewUser {
+    pub async fn create(
+        &self,
+        db: &DBConnection,
+        holder: Option<&AccountHolder>,
+    ) -> Result<Profile, AppError> {
+        if self.user_type == UserType::Admin {
+            ensure_admin_holder(holder)?;
+        }
+        PasswordPolicy::from_env().check(&self.password)?;
+        ensure_unique(db, &self.user_name, &self.email, &self.phone, None).await?;
+        let password = PasswordHasher::from_env()?.hash(&self.password)?;
+        let mut tx = db.begin().await?;
+        let user_id = sqlx::query_scalar!(
+            r#"
+                INSERT INTO users (first_name, last_name, user_name, email, password, phone, type, state, country)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                RETURNING id
+            "#,
+            self.first_name,
+            self.last_name,
+            self.user_name,
+            self.email,
+            password,
+            self.phone,
+            self.user_type.as_ref(),
+            self.state.as_ref(),
+            self.country.as_ref()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        PasswordPolicy::remember(&mut tx, user_id, &password).await?;
+        tx.commit().await?;
+        if let Err(e) = EmailVerification::send(db, user_id).await {
+            tracing::error!("{e} - verification email {user_id}");
+        }
+        Profile::find(db, user_id).await
+    }
+}
+
+/// fields left out are not changed, a new email or phone has to be verified again.
+/// a new user type changes the permissions, so the user has to log in again.
+#[derive(Serialize, Validate, Deserialize)]
+pub struct UpdateUser {
+    #[validate(length(min = 1, max = 255, message = "first-name-length"))]
+    pub first_name: Option<String>,
+    #[validate(length(min = 1, max = 255, message = "last-name-length"))]
+    pub last_name: Option<String>,
+    #[validate(length(min = 1, max = 255, message = "user-name-length"))]
+    pub user_name: Option<String>,
+    #[validate(email)]
+    pub email: Option<String>,
+    #[validate(phone)]
+    pub phone: Option<String>,
+    pub user_type: Option<UserType>,
+    pub state: Option<State>,
+    pub country: Option<Country>,
+}
+
+impl UpdateUser {
+    pub async fn update(
+        &self,
+        db: &DBConnection,
+        id: i32,
+        holder: Option<&AccountHolder>,
+    ) -> Result<Profile, AppError> {
+        let current = Profile::find(db, id).await?;
+        let user_type = self
+            .user_type
+            .filter(|user_type| *user_type != current.user_type);
+        if user_type.is_some_and(|user_type| {
+            user_type == UserType::Admin || current.user_type == UserType::Admin
+        }) {
+            ensure_admin_holder(holder)?;
+        }
+        ensure_unique(
+            db,
+            self.user_name.as_ref().unwrap_or(&current.user_name),
+            self.email.as_ref().unwrap_or(&current.email),
+            self.phone.as_ref().unwrap_or(&current.phone),
+            Some(id),
+        )
+        .await?;
+        let email = self.email.as_ref().filter(|email| **email != current.email);
+        let phone = self.phone.as_ref().filter(|phone| **phone != current.phone);
+        sqlx::query!(
+            r#"
+                UPDATE users SET
+                    first_name = COALESCE($1, first_name),
+                    last_name = COALESCE($2, last_name),
+                    user_name = COALESCE($3, user_name),
+                    email = COALESCE($4, email),
+                    email_verified_at = CASE WHEN $4::VARCHAR IS NULL THEN email_verified_at END,
+                    -- the cooldown of the old address doesn't hold back the new one
+                    email_verification_sent_at = CASE
+                        WHEN $4::VARCHAR IS NULL THEN email_verification_sent_at
+                    END,
+                    phone = COALESCE($5, phone),
+                    phone_verified_at = CASE WHEN $5::VARCHAR IS NULL THEN phone_verified_at END,
+                    type = COALESCE($6, type),
+                    state = COALESCE($7, state),
+                    country = COALESCE($8, country)
+                WHERE id = $9
+            "#,
+            self.first_name,
+            self.last_name,
+            self.user_name,
+            email,
+            phone,
+            user_type.as_ref().map(AsRef::<str>::as_ref),
+            self.state.as_ref().map(AsRef::<str>::as_ref),
+            self.country.as_ref().map(AsRef::<str>::as_ref),
+            id
+        )
+        .execute(db)
+        .await?;
+        if user_type.is_some() {
+            Session::revoke_all(db, id, None).await?;
+        }
+        // the user stays updated even if the verification can't be sent, it can be requested again
+        if email.is_some() {
+            if let Err(e) = EmailVerification::send(db, id).await {
+                tracing::error!("{e} - verification email {id}");
+            }
+        }
+        if let Some(phone) = phone {
+            let send_code = SendOtp {
+                phone: phone.clone(),
+                purpose: OtpPurpose::VerifyPhone,
+            };
+            if let Err(e) = send_code.attempt(db).await {
+                tracing::error!("{e} - verification sms {id}");
+            }
+        }
+        Profile::find(db, id).await
+    }
+}
+
+/// status transition of a user, see `UserStatus::can_change_to`
+#[derive(Serialize, Validate, Deserialize)]
+pub struct ChangeStatus {
+    pub status: UserStatus,
+    #[validate(length(min = 1, max = 500, message = "status-reason-length"))]
+    pub reason: String,
+}
+
+impl ChangeStatus {
+    /// the change is recorded in `user_status_changes`, all sessions of a user that is no longer active are revoked.
+    /// admins can't change their own status.
+    pub async fn attempt(
+        &self,
+        db: &DBConnection,
+        id: i32,
+        admin: &UserClaim,
+    ) -> Result<Profile, AppError> {
+        if id == admin.id {
+            return Err(Response(
+                "status-own-change".into(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        let mut tx = db.begin().await?;
+        let user = sqlx::query!(
+            r#"SELECT status AS "status: UserStatus" FROM users WHERE id = $1 FOR UPDATE"#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User".into()))?;
+        if !user.status.can_change_to(&self.status) {
+            return Err(Response(
+                t_args(
+                    "status-transition-invalid",
+                    &[
+                        ("from", user.status.as_ref().into()),
+                        ("to", self.status.as_ref().into()),
+                    ],
+                ),
+                StatusCode::CONFLICT,
+            ));
+        }
+        sqlx::query!(
+            "UPDATE users SET status = $1 WHERE id = $2",
+            self.status.as_ref(),
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+                INSERT INTO user_status_changes (user_id, from_status, to_status, reason, changed_by)
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+            id,
+            user.status.as_ref(),
+            self.status.as_ref(),
+            self.reason,
+            admin.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        if !self.status.is_active() {
+            Session::revoke_all(db, id, None).await?;
+        }
+        Profile::find(db, id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use actix_web::{FromRequest, HttpMessage};
+    use http::StatusCode;
+
+    use services::db::DBConnection;
+    use services::error::AppError;
+    use services::guard::AccountHolder;
+    use services::middleware::UserClaim;
+    use services::query_param::{Filter, QueryParams, WhereOpEnum};
+    use services::session::Session;
+    use services::users::{State, UserStatus, UserType};
+    use services::Country;
+
+    use super::{ChangeStatus, FilterColumns, NewUser, OrderColumns, UpdateUser, UserList};
+
+    async fn admin(pool: &DBConnection) -> UserClaim {
+        let id = sqlx::query_scalar!(
+            r#"
+                INSERT INTO users (first_name, last_name, user_name, email, password, phone, type, state, country)
+                VALUES ('Sam', 'Rusty', 'sam', 'sam@hgicrusade.com', '-', '+17780000000', 'Admin', 'GA', 'US')
+                RETURNING id
+            "#
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        UserClaim::new(
+            id,
+            1,
+            UserType::Admin,
+            "Sam".into(),
+            "Rusty".into(),
+            "sam@hgicrusade.com".into(),
+            None,
+        )
+    }
+
+    fn params(status: Vec<UserStatus>) -> QueryParams<FilterColumns, OrderColumns> {
+        QueryParams {
+            page: None,
+            limit: None,
+            filter: Some(FilterColumns {
+                first_name: None,
+                last_name: None,
+                user_name: None,
+                email: None,
+                user_type: None,
+                status: Some(Filter {
+                    val: status,
+                    op: WhereOpEnum::IN,
+                }),
+                state: None,
+                country: None,
+            }),
+            filter_type: None,
+            meta: None,
+            order: None,
+        }
+    }
+
+    fn change(status: UserStatus) -> ChangeStatus {
+        ChangeStatus {
+            status,
+            reason: "left the company".into(),
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_create_and_filter_users(pool: DBConnection) {
+        let form = NewUser {
+            first_name: "Jane".into(),
+            last_name: "Doe".into(),
+            user_name: "jane".into(),
+            email: "jane@hgicrusade.com".into(),
+            phone: "+17786860000".into(),
+            user_type: UserType::Associate,
+            state: State::BC,
+            country: Country::CA,
+            password: "Password-123".into(),
+        };
+        let admin = admin(&pool).await;
+        let jane = form.create(&pool, None).await.unwrap();
+        assert_eq!(jane.status, UserStatus::Active);
+        assert!(matches!(
+            form.create(&pool, None).await,
+            Err(AppError::Response(_, StatusCode::CONFLICT))
+        ));
+
+        change(UserStatus::Inactive)
+            .attempt(&pool, jane.id, &admin)
+            .await
+            .unwrap();
+        let active = UserList::find(&pool, params(vec![UserStatus::Active]))
+            .await
+            .unwrap();
+        assert_eq!(active.items.len(), 2);
+        assert!(active.items.iter().all(|user| user.user_name != "jane"));
+        let all = UserList::find(
+            &pool,
+            params(vec![UserStatus::Active, UserStatus::Inactive]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(all.items.len(), 3);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_terminate_and_revoke_sessions(pool: DBConnection) {
+        let id = sqlx::query_scalar!("SELECT id FROM users WHERE user_name = 'hubert'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let admin = admin(&pool).await;
+        let session = Session::create(&pool, id, UserClaim::expires_at())
+            .await
+            .unwrap();
+        let user = change(UserStatus::Terminated)
+            .attempt(&pool, id, &admin)
+            .await
+            .unwrap();
+        assert_eq!(user.status, UserStatus::Terminated);
+        assert!(!Session::is_active(&pool, session).await.unwrap());
+        let reason = sqlx::query_scalar!(
+            "SELECT reason FROM user_status_changes WHERE user_id = $1",
+            id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(reason, "left the company");
+
+        // terminated is final
+        let result = change(UserStatus::Active).attempt(&pool, id, &admin).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::CONFLICT))
+        ));
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_verify_new_contacts_and_revoke_sessions_on_type_change(pool: DBConnection) {
+        let session = Session::create(&pool, 1, UserClaim::expires_at())
+            .await
+            .unwrap();
+        // a verification email was just sent to the old address
+        sqlx::query!("UPDATE users SET email_verification_sent_at = NOW() WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let form = UpdateUser {
+            first_name: None,
+            last_name: None,
+            user_name: None,
+            email: Some("bert@hgicrusade.com".into()),
+            phone: Some("+17786860001".into()),
+            user_type: Some(UserType::Admin),
+            state: None,
+            country: None,
+        };
+        let holder = AccountHolder(admin(&pool).await);
+        let user = form.update(&pool, 1, Some(&holder)).await.unwrap();
+        assert_eq!(user.user_type, UserType::Admin);
+        assert!(user.email_verified_at.is_none());
+        assert!(user.phone_verified_at.is_none());
+        assert!(!Session::is_active(&pool, session).await.unwrap());
+        let sent = sqlx::query!(
+            r#"
+                SELECT
+                    (SELECT COUNT(*) FROM single_use_tokens WHERE user_id = 1) AS "emails!",
+                    (SELECT COUNT(*) FROM one_time_passcodes WHERE user_id = 1) AS "codes!"
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(sent.emails, 1);
+        assert_eq!(sent.codes, 1);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_only_let_admins_grant_the_admin_type(pool: DBConnection) {
+        let promote = UpdateUser {
+            first_name: None,
+            last_name: None,
+            user_name: None,
+            email: None,
+            phone: None,
+            user_type: Some(UserType::Admin),
+            state: None,
+            country: None,
+        };
+        let admin = admin(&pool).await;
+        // an admin's key scoped to users.manage
+        let mut key = admin
+            .clone()
+            .with_access(vec![], vec!["users.manage".into()]);
+        key.api_key_id = Some(1);
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(key);
+        let holder = Option::<AccountHolder>::extract(&req).await.unwrap();
+        assert!(holder.is_none());
+        let result = promote.update(&pool, 1, holder.as_ref()).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::FORBIDDEN))
+        ));
+
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(admin);
+        let holder = Option::<AccountHolder>::extract(&req).await.unwrap();
+        let user = promote.update(&pool, 1, holder.as_ref()).await.unwrap();
+        assert_eq!(user.user_type, UserType::Admin);
+    }
+}
@@ -0,0 +1,188 @@
+use std::env::var;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use validator::Validate as ActixValidator;
+
+use integration::sendgrid::Recipient;
+use services::db::DBConnection;
+use services::error::AppError;
+use services::i18n::t_args;
+use AppError::Response;
+
+use crate::single_use_token::{SingleUseToken, TokenPurpose};
+
+/// seconds to wait before another verification email can be sent
+const RESEND_COOLDOWN_SECONDS: i64 = 60;
+
+/// when `REQUIRE_EMAIL_VERIFICATION` is `true`, users can't log in until their email is verified.
+pub(crate) fn ensure_verified(email_verified_at: Option<NaiveDateTime>) -> Result<(), AppError> {
+    let required = var("REQUIRE_EMAIL_VERIFICATION")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    if required && email_verified_at.is_none() {
+        return Err(Response("email-not-verified".into(), StatusCode::FORBIDDEN));
+    }
+    Ok(())
+}
+
+pub struct EmailVerification;
+
+impl EmailVerification {
+    /// email a single use verification link to the user, a new link replaces the older ones
+    pub async fn send(db: &DBConnection, user_id: i32) -> Result<(), AppError> {
+        let user = sqlx::query!(
+            r#"
+                SELECT first_name, last_name, email, email_verified_at, email_verification_sent_at
+                FROM users
+                WHERE id = $1
+            "#,
+            user_id
+        )
+        .fetch_one(db)
+        .await?;
+        if user.email_verified_at.is_some() {
+            return Ok(());
+        }
+        if let Some(sent_at) = user.email_verification_sent_at {
+            let wait =
+                sent_at + Duration::seconds(RESEND_COOLDOWN_SECONDS) - Utc::now().naive_utc();
+            if wait > Duration::zero() {
+                return Err(Response(
+                    t_args(
+                        "wait-before-new-email",
+                        &[("seconds", (wait.num_seconds() + 1).to_string())],
+                    ),
+                    StatusCode::TOO_MANY_REQUESTS,
+                ));
+            }
+        }
+        sqlx::query!(
+            "UPDATE users SET email_verification_sent_at = NOW() WHERE id = $1",
+            user_id
+        )
+        .execute(db)
+        .await?;
+
+        let link = SingleUseToken::link(db, user_id, TokenPurpose::EmailVerification).await?;
+        let message = format!(
+            r#"
+                <p>Hi {} {},</p>
+                <p>Please <a href='{link}'>click here</a> to verify your email address.</p>
+            "#,
+            user.first_name, user.last_name
+        );
+        integration::sendgrid::Email::new(
+            Recipient::new(user.email, user.first_name, user.last_name),
+            "Verify Your Email",
+            message,
+        )
+        .send()
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct ResendVerification {
+    #[validate(email)]
+    pub email: String,
+}
+
+impl ResendVerification {
+    pub async fn attempt(&self, db: &DBConnection) -> Result<(), AppError> {
+        let user_id = sqlx::query_scalar!("SELECT id FROM users WHERE email = $1", self.email)
+            .fetch_optional(db)
+            .await?;
+        match user_id {
+            Some(user_id) => EmailVerification::send(db, user_id).await,
+            // same response for unknown emails
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, ActixValidator, Deserialize)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+impl VerifyEmail {
+    pub async fn attempt(&self, db: &DBConnection) -> Result<(), AppError> {
+        let user_id = SingleUseToken::consume(db, TokenPurpose::EmailVerification, &self.token)
+            .await?
+            .ok_or_else(|| Response("invalid-token".into(), StatusCode::BAD_REQUEST))?;
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+            user_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use services::db::DBConnection;
+    use services::encryption::Jwt;
+    use services::load_env;
+    use services::middleware::UserClaim;
+    use services::users::UserType;
+
+    use super::*;
+
+    fn form(link: &str) -> VerifyEmail {
+        VerifyEmail {
+            token: link.rsplit('/').next().unwrap().to_string(),
+        }
+    }
+
+    async fn verified_at(pool: &DBConnection) -> Option<NaiveDateTime> {
+        sqlx::query_scalar!("SELECT email_verified_at FROM users WHERE id = 1")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_verify_email_once_with_latest_link(pool: DBConnection) {
+        let first = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::EmailVerification)
+                .await
+                .unwrap(),
+        );
+        let second = form(
+            &SingleUseToken::link(&pool, 1, TokenPurpose::EmailVerification)
+                .await
+                .unwrap(),
+        );
+        assert!(first.attempt(&pool).await.is_err());
+        assert!(second.attempt(&pool).await.is_ok());
+        assert!(verified_at(&pool).await.is_some());
+        assert!(second.attempt(&pool).await.is_err());
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-user"))]
+    async fn should_reject_session_token(pool: DBConnection) {
+        load_env(None);
+        let claim = UserClaim::new(
+            1,
+            1,
+            UserType::Associate,
+            "Hubert".into(),
+            "Humphrey".into(),
+            "hubert@hgicrusade.com".into(),
+            None,
+        );
+        let form = VerifyEmail {
+            token: Jwt::encode(&claim).unwrap(),
+        };
+        assert!(matches!(
+            form.attempt(&pool).await,
+            Err(AppError::Response(_, StatusCode::BAD_REQUEST))
+        ));
+        assert!(verified_at(&pool).await.is_none());
+    }
+}
@@ -2,37 +2,36 @@ use std::convert::Into;
 use std::fmt::Debug;
 use std::string::ToString;
 
-use chrono::NaiveDate;
+use async_trait::async_trait;
 use scooby::postgres::select;
 use serde::{Deserialize, Serialize};
 use struct_iterable::Iterable;
 use validator::Validate;
 
+use services::crud::policy::{Owned, Policy};
+use services::crud::traits::Crud;
 use services::db::DBConnection;
 use services::error::AppError;
-use services::query_param::{DateFilter, EnumFilter, OrderBy, QueryParams, StringFilter};
-use services::Status;
+use services::query_param::{OrderBy, Page, QueryParams, StringFilter};
 
 #[derive(Deserialize, Serialize, Debug, Iterable)]
 pub struct FilterColumns {
-    pub content: StringFilter,
-    pub status: EnumFilter<Status>,
-    pub effective_date: DateFilter,
+    pub name: StringFilter,
+    pub country: StringFilter,
 }
 
 #[derive(Deserialize, Serialize, Debug, Iterable)]
 pub struct OrderColumns {
     pub id: OrderBy,
-    pub content: OrderBy,
-    pub status: OrderBy,
-    pub effective_date: OrderBy,
+    pub name: OrderBy,
+    pub country: OrderBy,
 }
 
 #[derive(Serialize, Deserialize, Validate, sqlx::FromRow, PartialEq, Debug)]
 pub struct AmaList {
     pub id: i32,
-    pub status: Status,
-    pub effective_date: NaiveDate,
+    pub name: String,
+    pub country: String,
 }
 
 #[derive(Serialize, Deserialize, Validate, sqlx::FromRow, PartialEq, Debug)]
@@ -43,30 +42,39 @@ pub struct Ama {
     pub description: String,
 }
 
-impl Ama {
-    pub async fn create(&self, db: &DBConnection) -> Result<Self, AppError> {
+impl Owned for Ama {}
+
+#[async_trait]
+impl Crud<Self, FilterColumns, OrderColumns, i32, AmaList> for Ama {
+    async fn create(&self, db: &DBConnection, policy: &Policy) -> Result<Self, AppError> {
         let result = sqlx::query_as!(
             Self,
             r#"
                 WITH new_inserted AS (
-                    INSERT INTO ama (name, description, country) VALUES ($1, $2, $3) RETURNING *
+                    INSERT INTO ama (name, description, country, created_by) VALUES ($1, $2, $3, $4) RETURNING *
                 )
                 SELECT id, name, description, country FROM new_inserted
             "#,
             self.name,
+            self.description,
             self.country,
-            self.description
+            policy.user_id
         )
         .fetch_one(db)
         .await?;
         Ok(result)
     }
 
-    pub async fn find_by_id(db: &DBConnection, id: i32) -> Result<Self, AppError> {
+    async fn find_by_id(db: &DBConnection, id: i32, policy: &Policy) -> Result<Self, AppError> {
         let result = sqlx::query_as!(
             Self,
-            r#"SELECT id, name, description, country FROM ama WHERE id = $1"#,
-            id
+            r#"
+                SELECT id, name, description, country FROM ama
+                WHERE id = $1 AND ($2 OR created_by = $3)
+            "#,
+            id,
+            policy.bypass,
+            policy.user_id
         )
         .fetch_optional(db)
         .await?;
@@ -76,26 +84,31 @@ impl Ama {
         }
     }
 
-    pub async fn find(
+    async fn find(
         db: &DBConnection,
         params: QueryParams<FilterColumns, OrderColumns>,
-    ) -> Result<Vec<AmaList>, AppError> {
-        let query = select("id, status, effective_date").from("ama");
-        let (query, args, _) = params.build_query(query, "", 20)?;
+        policy: &Policy,
+    ) -> Result<Page<AmaList>, AppError> {
+        let query = select("id, name, country").from("ama");
+        let (query, args, _, limit) = params.build_scoped_query::<Self>(query, "", 20, policy)?;
         let sql = query.to_string();
         let query = sqlx::query_as_with(&sql, args);
         let result: Vec<AmaList> = query.fetch_all(db).await?;
-        Ok(result)
+        Ok(params.page(result, limit))
     }
 
-    pub async fn update(&self, db: &DBConnection, id: i32) -> Result<(), AppError> {
+    async fn update(&self, db: &DBConnection, id: i32, policy: &Policy) -> Result<(), AppError> {
         let rows_affected = sqlx::query!(
-            // id, content, content, description, country
-            "UPDATE ama SET name = $1, description = $2, country = $3 WHERE id = $4",
+            r#"
+                UPDATE ama SET name = $1, description = $2, country = $3
+                WHERE id = $4 AND ($5 OR created_by = $6)
+            "#,
             &self.name,
             self.description,
             self.country,
-            id
+            id,
+            policy.bypass,
+            policy.user_id
         )
         .execute(db)
         .await?
@@ -107,11 +120,16 @@ impl Ama {
         }
     }
 
-    pub async fn delete(db: &DBConnection, id: i32) -> Result<(), AppError> {
-        let rows_affected = sqlx::query!("DELETE FROM ama WHERE id = $1", id)
-            .execute(db)
-            .await?
-            .rows_affected();
+    async fn delete(db: &DBConnection, id: i32, policy: &Policy) -> Result<(), AppError> {
+        let rows_affected = sqlx::query!(
+            "DELETE FROM ama WHERE id = $1 AND ($2 OR created_by = $3)",
+            id,
+            policy.bypass,
+            policy.user_id
+        )
+        .execute(db)
+        .await?
+        .rows_affected();
         if rows_affected > 0 {
             Ok(())
         } else {
@@ -122,23 +140,48 @@ impl Ama {
 
 #[cfg(test)]
 mod tests {
+    use services::crud::policy::Policy;
+    use services::crud::traits::Crud;
     use services::db::DBConnection;
     use services::query_param::QueryParams;
 
-    use super::Ama;
+    use super::{Ama, FilterColumns, OrderColumns};
 
-    #[sqlx::test(migrations = "../../migrations", fixtures("setup-ama"))]
-    async fn should_pass_find_all(pool: DBConnection) {
-        let params = QueryParams {
+    fn params() -> QueryParams<FilterColumns, OrderColumns> {
+        QueryParams {
             page: Some(1),
             limit: Some(10),
             filter: None,
             filter_type: None,
             meta: None,
             order: None,
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-ama"))]
+    async fn should_pass_find_all(pool: DBConnection) {
+        let admin = Policy {
+            user_id: 1,
+            bypass: true,
         };
-        let result = Ama::find(&pool, params).await;
+        let result = Ama::find(&pool, params(), &admin).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap().len(), 1);
+        assert_eq!(result.unwrap().items.len(), 1);
+    }
+
+    #[sqlx::test(migrations = "../../migrations", fixtures("setup-ama"))]
+    async fn should_hide_records_of_other_users(pool: DBConnection) {
+        let associate = Policy {
+            user_id: 2,
+            bypass: false,
+        };
+        let result = Ama::find(&pool, params(), &associate).await;
+        assert_eq!(result.unwrap().items.len(), 0);
+        let id = sqlx::query_scalar!("SELECT id FROM ama LIMIT 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(Ama::find_by_id(&pool, id, &associate).await.is_err());
+        assert!(Ama::delete(&pool, id, &associate).await.is_err());
     }
 }
@@ -1,6 +1,6 @@
 BEGIN;
 
-INSERT INTO ama (content, effective_date, status)
-VALUES ('This is a test content', DATE('2023-01-01'), 'Active');
+INSERT INTO ama (name, country, description)
+VALUES ('Test', 'US', 'This is a test content');
 
-COMMIT;
\ No newline at end of file
+COMMIT;
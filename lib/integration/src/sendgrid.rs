@@ -110,12 +110,8 @@ impl<'a> Email<'a> {
         let mut header = HeaderMap::new();
         header.insert(
             "Authorization",
-            HeaderValue::from_str(&format!("Bearer {}", api_key)).map_err(|_| {
-                AppError::Response(
-                    "Invalid header value".to_string(),
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                )
-            })?,
+            HeaderValue::from_str(&format!("Bearer {}", api_key))
+                .map_err(|_| AppError::Message("Invalid header value".to_string()))?,
         );
         header.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         let mut cc = json!({});
@@ -1,5 +1,3 @@
-use std::io::ErrorKind;
-
 use aws_sdk_sqs::Client;
 use serde_json::json;
 
@@ -23,7 +21,7 @@ impl Sqs {
         // if cfg!(test) {
         //     return Ok(());
         // }
-        println!("message {message}");
+        tracing::debug!("{message} - sqs send");
         let response = self
             .client
             .send_message()
@@ -32,11 +30,14 @@ impl Sqs {
             .send()
             .await;
         match response {
-            Ok(e) => {
-                println!("{:?}", e);
+            Ok(output) => {
+                tracing::debug!("{:?} - sqs sent", output.message_id());
                 Ok(())
             }
-            Err(e) => Err(std::io::Error::new(ErrorKind::Other, e.to_string())),
+            Err(e) => {
+                tracing::error!("{e} - sqs send");
+                Err(std::io::Error::other(e.to_string()))
+            }
         }
     }
 }
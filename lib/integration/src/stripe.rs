@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::convert::Into;
 use std::string::ToString;
+use std::time::Duration;
 
 use reqwest::header::{HeaderMap, AUTHORIZATION};
 use reqwest::{Client, StatusCode};
@@ -12,6 +13,8 @@ use strum_macros::AsRefStr;
 use services::error::AppError;
 
 const STRIPE_API_URL: &str = "https://api.stripe.com/v1";
+/// a charge that doesn't answer in time fails, the registration is rolled back
+const TIMEOUT_SECONDS: u64 = 30;
 
 #[derive(Deserialize, Serialize)]
 pub struct Charge {
@@ -29,7 +32,12 @@ struct StripeError {
     error: ErrorResponse,
 }
 
-pub struct Payment;
+/// Stripe accounts charged for each country
+pub struct Payment {
+    pub api_url: String,
+    pub secret_key_ca: String,
+    pub secret_key_us: String,
+}
 
 #[derive(AsRefStr)]
 pub enum Currency {
@@ -38,18 +46,30 @@ pub enum Currency {
 }
 
 impl Payment {
-    /// charge user for the amount specified in the currency of the country (USD or CAD)
+    /// keys from `STRIPE_SECRET_KEY_CA` and `STRIPE_SECRET_KEY_US`
+    pub fn from_env() -> Result<Self, AppError> {
+        Ok(Self {
+            api_url: STRIPE_API_URL.into(),
+            secret_key_ca: std::env::var("STRIPE_SECRET_KEY_CA")?,
+            secret_key_us: std::env::var("STRIPE_SECRET_KEY_US")?,
+        })
+    }
+
+    /// charge user for the amount in cents, in the currency of the country (USD or CAD).
+    /// card tokens can only be charged once, so the token is the idempotency key
+    /// and a retried charge returns the first one instead of charging again.
     pub async fn charge(
+        &self,
         _first_name: &str,
         _last_name: &str,
         email: &str,
-        amount: f32,
+        amount_cents: i64,
         country: &Country,
         source: &str,
     ) -> Result<String, AppError> {
         let mut data_map = HashMap::new();
         data_map.insert("receipt_email", email.to_string());
-        data_map.insert("amount", (amount * 100.00).to_string());
+        data_map.insert("amount", amount_cents.to_string());
         data_map.insert("source", source.to_string());
 
         // todo:: fix metadata issue, it was failing
@@ -59,30 +79,41 @@ impl Payment {
         // meta_data.insert("last_name", last_name.to_string());
         // data_map.insert("metadata", serde_json::to_string(&meta_data)?);
 
-        let body = Self::make_post_call("/charges", &mut data_map, country).await?;
+        let body = self
+            .make_post_call("/charges", &mut data_map, country, source)
+            .await?;
         let response: Charge = serde_json::from_str(&body)?;
         Ok(response.id)
     }
 
     async fn make_post_call(
+        &self,
         endpoint: &str,
         data_map: &mut HashMap<&str, String>,
         country: &Country,
+        idempotency_key: &str,
     ) -> Result<String, AppError> {
         let (secret_key, currency) = match country {
-            Country::CA => (std::env::var("STRIPE_SECRET_KEY_CA")?, Currency::CAD),
-            Country::US => (std::env::var("STRIPE_SECRET_KEY_US")?, Currency::USD),
+            Country::CA => (&self.secret_key_ca, Currency::CAD),
+            Country::US => (&self.secret_key_us, Currency::USD),
         };
         data_map.insert("currency", currency.as_ref().to_string());
         let secret_key = secret_key
             .parse()
             .map_err(|_| AppError::Message("Invalid Stripe Key".into()))?;
+        let idempotency_key = idempotency_key
+            .parse()
+            .map_err(|_| AppError::Message("Invalid idempotency key".into()))?;
 
-        let client = Client::new();
+        let client = Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+            .build()
+            .map_err(|e| AppError::Message(e.to_string()))?;
         let mut headers = HeaderMap::new();
         headers.insert(AUTHORIZATION, secret_key);
+        headers.insert("Idempotency-Key", idempotency_key);
         let response = client
-            .post(STRIPE_API_URL.clone().to_owned() + endpoint)
+            .post(format!("{}{endpoint}", self.api_url))
             .form(&data_map)
             .headers(headers)
             .send()
@@ -0,0 +1,112 @@
+# English messages, every id must also be in fr.ftl
+
+## titles of the error responses by status
+title-400 = Bad Request
+title-401 = Unauthorized
+title-403 = Forbidden
+title-404 = Not Found
+title-409 = Conflict
+title-413 = Payload Too Large
+title-415 = Unsupported Media Type
+title-422 = Unprocessable Entity
+title-423 = Locked
+title-429 = Too Many Requests
+title-500 = Internal Server Error
+title-503 = Service Unavailable
+
+## errors
+unexpected-error = An unexpected error occurred
+not-found = { $entity } Not Found
+validation-failed = Validation failed
+content-type-json = Content type must be application/json
+record-not-found = Record Not Found
+record-already-exists = Record already exists
+field-already-exists = { $field } already exists
+reference-not-found = Referenced record doesn't exist
+still-referenced = Record is still in use
+invalid-value = Invalid value
+try-again = Please try again
+too-many-requests = Too many requests, please try again later
+wait-before-retry = Please wait { $seconds } seconds before trying again
+invalid-parameter = Invalid parameter for { $name }
+invalid-parameter-range = Invalid parameter for { $name }, from and to must be set
+invalid-parameter-bool = Invalid parameter for { $name }, pass either true or false
+invalid-parameter-status = Invalid parameter for { $name }, pass either Active or Inactive
+
+## validation of fields, by validator
+validation-email = Must be a valid email
+validation-phone = Must be a valid phone number
+validation-url = Must be a valid url
+validation-length = Has an invalid length
+validation-range = Is out of range
+validation-required = Is required
+validation-must_match = Doesn't match
+validation-unique = Is already taken
+validation-exists = Doesn't exist
+validation-invalid = Is invalid
+
+## forms
+passwords-mismatch = Passwords do not match
+password-min-length = Password must be at least 8 characters long
+first-name-length = First name must be 1 to 255 characters long
+last-name-length = Last name must be 1 to 255 characters long
+card-token-invalid = Must be a Stripe card token
+card-last4-invalid = Must be the last 4 digits of the card
+user-name-length = User name must be 1 to 255 characters long
+code-length = Code must be 6 digits
+user-name-taken = An account with this user name already exists
+email-taken = An account with this email already exists
+phone-taken = An account with this phone already exists
+
+## authentication
+login-required = Login required
+session-expired = Session expired
+invalid-token = Invalid Token
+invalid-credentials = Invalid username or password
+account-not-active = Your account is not active
+account-locked = Account is temporarily locked. Check your email to unlock it
+account-locked-try-later = Account is temporarily locked. Reset your password to unlock it or try again later
+too-many-login-attempts = Too many failed login attempts, please try again later
+email-not-verified = Please verify your email address before logging in
+invalid-code = Invalid or expired code
+too-many-codes = Too many codes requested, please try again later
+invalid-link = Invalid or expired link
+email-not-found = User with this email does not exist
+wait-before-new-code = Please wait { $seconds } seconds before requesting a new code
+wait-before-new-email = Please wait { $seconds } seconds before requesting another email
+oidc-login-failed = Sign in with the identity provider failed
+oidc-no-account = No account is linked to this identity
+oidc-code-required = Code is required
+oidc-state-required = State is required
+oidc-request-invalid = Login request is invalid or has expired
+api-key-not-allowed = This action can't be taken with an API key
+api-key-scopes-not-granted = The user of the key doesn't have the scopes: { $scopes }
+api-key-name-length = Name must be 1 to 100 characters long
+api-key-scopes-required = At least one scope is required
+api-key-expiry-past = Expiry date must be in the future
+api-key-unknown-scopes = Unknown scopes: { $scopes }
+
+## permissions
+permission-required = You don't have the { $name } permission
+impersonation-not-allowed = This action is not allowed while impersonating a user
+impersonation-reason-length = Reason must be at most 500 characters long
+impersonation-admins-only = Only admins can impersonate users
+impersonation-admin-target = Admins can't be impersonated
+impersonation-not-active = You are not impersonating a user
+
+## user management
+status-reason-length = Reason must be 1 to 500 characters long
+admin-type-not-allowed = Only an admin signed in as themselves can give or remove the Admin type
+status-own-change = You can't change your own status
+status-transition-invalid = Status can't change from { $from } to { $to }
+
+## passwords
+current-password-incorrect = Current password is incorrect
+password-length = Password must be between { $min } and { $max } characters
+password-must-contain = Password must contain { $rules }
+password-lowercase = a lowercase letter
+password-uppercase = an uppercase letter
+password-digit = a number
+password-symbol = a symbol
+password-too-common = This password is too common, please choose another one
+password-history = Password can't be one of your last { $count } passwords
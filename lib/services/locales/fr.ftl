@@ -0,0 +1,112 @@
+# Messages en français, chaque id doit aussi être dans en.ftl
+
+## titres des réponses d'erreur par statut
+title-400 = Requête invalide
+title-401 = Non authentifié
+title-403 = Accès refusé
+title-404 = Introuvable
+title-409 = Conflit
+title-413 = Contenu trop volumineux
+title-415 = Type de contenu non pris en charge
+title-422 = Entité non traitable
+title-423 = Verrouillé
+title-429 = Trop de requêtes
+title-500 = Erreur interne du serveur
+title-503 = Service indisponible
+
+## erreurs
+unexpected-error = Une erreur inattendue s'est produite
+not-found = { $entity } introuvable
+validation-failed = La validation a échoué
+content-type-json = Le type de contenu doit être application/json
+record-not-found = Enregistrement introuvable
+record-already-exists = L'enregistrement existe déjà
+field-already-exists = { $field } existe déjà
+reference-not-found = L'enregistrement référencé n'existe pas
+still-referenced = L'enregistrement est encore utilisé
+invalid-value = Valeur invalide
+try-again = Veuillez réessayer
+too-many-requests = Trop de requêtes, veuillez réessayer plus tard
+wait-before-retry = Veuillez attendre { $seconds } secondes avant de réessayer
+invalid-parameter = Paramètre invalide pour { $name }
+invalid-parameter-range = Paramètre invalide pour { $name }, le début et la fin sont obligatoires
+invalid-parameter-bool = Paramètre invalide pour { $name }, utilisez true ou false
+invalid-parameter-status = Paramètre invalide pour { $name }, utilisez Active ou Inactive
+
+## validation des champs, par validateur
+validation-email = Doit être un courriel valide
+validation-phone = Doit être un numéro de téléphone valide
+validation-url = Doit être une URL valide
+validation-length = La longueur est invalide
+validation-range = Hors des limites permises
+validation-required = Obligatoire
+validation-must_match = Ne correspond pas
+validation-unique = Déjà utilisé
+validation-exists = N'existe pas
+validation-invalid = Invalide
+
+## formulaires
+passwords-mismatch = Les mots de passe ne correspondent pas
+password-min-length = Le mot de passe doit contenir au moins 8 caractères
+first-name-length = Le prénom doit contenir de 1 à 255 caractères
+last-name-length = Le nom doit contenir de 1 à 255 caractères
+card-token-invalid = Doit être un jeton de carte Stripe
+card-last4-invalid = Doit être les 4 derniers chiffres de la carte
+user-name-length = Le nom d'utilisateur doit contenir de 1 à 255 caractères
+code-length = Le code doit contenir 6 chiffres
+user-name-taken = Un compte avec ce nom d'utilisateur existe déjà
+email-taken = Un compte avec ce courriel existe déjà
+phone-taken = Un compte avec ce numéro de téléphone existe déjà
+
+## authentification
+login-required = Connexion requise
+session-expired = Session expirée
+invalid-token = Jeton invalide
+invalid-credentials = Nom d'utilisateur ou mot de passe invalide
+account-not-active = Votre compte n'est pas actif
+account-locked = Le compte est temporairement verrouillé. Consultez vos courriels pour le déverrouiller
+account-locked-try-later = Le compte est temporairement verrouillé. Réinitialisez votre mot de passe pour le déverrouiller ou réessayez plus tard
+too-many-login-attempts = Trop de tentatives de connexion échouées, veuillez réessayer plus tard
+email-not-verified = Veuillez vérifier votre adresse courriel avant de vous connecter
+invalid-code = Code invalide ou expiré
+too-many-codes = Trop de codes demandés, veuillez réessayer plus tard
+invalid-link = Lien invalide ou expiré
+email-not-found = Aucun utilisateur n'a ce courriel
+wait-before-new-code = Veuillez attendre { $seconds } secondes avant de demander un nouveau code
+wait-before-new-email = Veuillez attendre { $seconds } secondes avant de demander un autre courriel
+oidc-login-failed = La connexion avec le fournisseur d'identité a échoué
+oidc-no-account = Aucun compte n'est lié à cette identité
+oidc-code-required = Le code est obligatoire
+oidc-state-required = L'état est obligatoire
+oidc-request-invalid = La demande de connexion est invalide ou a expiré
+api-key-not-allowed = Cette action ne peut pas être effectuée avec une clé d'API
+api-key-scopes-not-granted = L'utilisateur de la clé n'a pas les permissions : { $scopes }
+api-key-name-length = Le nom doit contenir de 1 à 100 caractères
+api-key-scopes-required = Au moins une permission est obligatoire
+api-key-expiry-past = La date d'expiration doit être dans le futur
+api-key-unknown-scopes = Permissions inconnues : { $scopes }
+
+## permissions
+permission-required = Vous n'avez pas la permission { $name }
+impersonation-not-allowed = Cette action n'est pas permise en se faisant passer pour un utilisateur
+impersonation-reason-length = La raison doit contenir au plus 500 caractères
+impersonation-admins-only = Seuls les administrateurs peuvent se faire passer pour un utilisateur
+impersonation-admin-target = On ne peut pas se faire passer pour un administrateur
+impersonation-not-active = Vous ne vous faites pas passer pour un utilisateur
+
+## gestion des utilisateurs
+status-reason-length = La raison doit contenir de 1 à 500 caractères
+admin-type-not-allowed = Seul un administrateur connecté en son nom peut donner ou retirer le type Admin
+status-own-change = Vous ne pouvez pas changer votre propre statut
+status-transition-invalid = Le statut ne peut pas passer de { $from } à { $to }
+
+## mots de passe
+current-password-incorrect = Le mot de passe actuel est incorrect
+password-length = Le mot de passe doit contenir entre { $min } et { $max } caractères
+password-must-contain = Le mot de passe doit contenir { $rules }
+password-lowercase = une lettre minuscule
+password-uppercase = une lettre majuscule
+password-digit = un chiffre
+password-symbol = un symbole
+password-too-common = Ce mot de passe est trop courant, veuillez en choisir un autre
+password-history = Le mot de passe ne peut pas être l'un de vos { $count } derniers
@@ -0,0 +1,363 @@
+use actix_web::http::header::HeaderMap;
+use chrono::{NaiveDateTime, Utc};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::db::DBConnection;
+use crate::encryption::Token;
+use crate::error::AppError;
+use crate::i18n::{t_args, Locale};
+use crate::middleware::UserClaim;
+use crate::users::UserType;
+use AppError::Response;
+
+/// every key starts with it so leaked keys are easy to find, e.g. `ck_1a2b3c4d_<secret>`
+pub const KEY_PREFIX: &str = "ck_";
+/// prefixes drawn before giving up, a collision of 8 hex characters is already rare
+const PREFIX_ATTEMPTS: usize = 3;
+
+/// Key of a machine to machine client, it acts as `user_id` but only with the permissions in `scopes`.
+#[derive(Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub user_id: i32,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct NewApiKey {
+    #[validate(length(min = 1, max = 100, message = "api-key-name-length"))]
+    pub name: String,
+    /// user the key acts as, the admin creating it by default
+    pub user_id: Option<i32>,
+    #[validate(length(min = 1, message = "api-key-scopes-required"))]
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// returned once on creation, only the hash of `key` is stored
+#[derive(Serialize, Deserialize)]
+pub struct IssuedApiKey {
+    pub id: i32,
+    pub prefix: String,
+    pub key: String,
+}
+
+impl NewApiKey {
+    pub async fn create(
+        &self,
+        db: &DBConnection,
+        created_by: i32,
+    ) -> Result<IssuedApiKey, AppError> {
+        if self
+            .expires_at
+            .is_some_and(|at| at <= Utc::now().naive_utc())
+        {
+            return Err(Response(
+                "api-key-expiry-past".into(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        let unknown = sqlx::query_scalar!(
+            r#"
+                SELECT scope AS "scope!" FROM UNNEST($1::TEXT[]) AS scope
+                WHERE scope NOT IN (SELECT name FROM permissions)
+            "#,
+            &self.scopes
+        )
+        .fetch_all(db)
+        .await?;
+        if !unknown.is_empty() {
+            return Err(Response(
+                t_args("api-key-unknown-scopes", &[("scopes", unknown.join(", "))]),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        let user_id = self.user_id.unwrap_or(created_by);
+        let user_type = sqlx::query!(
+            r#"SELECT type AS "user_type: UserType" FROM users WHERE id = $1"#,
+            user_id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User".into()))?
+        .user_type;
+        // a key can't do more than its user, admins have every permission
+        if user_type != UserType::Admin {
+            let not_granted = sqlx::query_scalar!(
+                r#"
+                    SELECT scope AS "scope!" FROM UNNEST($1::TEXT[]) AS scope
+                    WHERE scope NOT IN (
+                        SELECT p.name FROM permissions p
+                        JOIN role_permissions rp ON rp.permission_id = p.id
+                        JOIN roles r ON r.id = rp.role_id
+                        WHERE r.name = $3
+                           OR EXISTS(SELECT 1 FROM user_roles ur WHERE ur.role_id = r.id AND ur.user_id = $2)
+                    )
+                "#,
+                &self.scopes,
+                user_id,
+                user_type.as_ref()
+            )
+            .fetch_all(db)
+            .await?;
+            if !not_granted.is_empty() {
+                return Err(Response(
+                    t_args(
+                        "api-key-scopes-not-granted",
+                        &[("scopes", not_granted.join(", "))],
+                    ),
+                    StatusCode::FORBIDDEN,
+                ));
+            }
+        }
+
+        // the prefix is unique, a new one is drawn on the rare collision
+        for _ in 0..PREFIX_ATTEMPTS {
+            let prefix = Token::generate()[..8].to_string();
+            let key = format!("{KEY_PREFIX}{prefix}_{}", Token::generate());
+            let inserted = sqlx::query_scalar!(
+                r#"
+                    INSERT INTO api_keys (name, prefix, key_hash, user_id, scopes, expires_at, created_by)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    ON CONFLICT (prefix) DO NOTHING
+                    RETURNING id
+                "#,
+                self.name,
+                prefix,
+                Token::hash(&key),
+                user_id,
+                &self.scopes,
+                self.expires_at,
+                created_by
+            )
+            .fetch_optional(db)
+            .await?;
+            if let Some(id) = inserted {
+                return Ok(IssuedApiKey { id, prefix, key });
+            }
+        }
+        Err(Response(
+            "try-again".into(),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ))
+    }
+}
+
+impl ApiKey {
+    pub async fn find(db: &DBConnection) -> Result<Vec<Self>, AppError> {
+        let keys = sqlx::query_as!(
+            Self,
+            r#"
+                SELECT id, name, prefix, user_id, scopes, expires_at, last_used_at, revoked_at, created_at
+                FROM api_keys
+                ORDER BY id
+            "#
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(keys)
+    }
+
+    pub async fn find_by_id(db: &DBConnection, id: i32) -> Result<Self, AppError> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT id, name, prefix, user_id, scopes, expires_at, last_used_at, revoked_at, created_at
+                FROM api_keys
+                WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("API key".into()))
+    }
+
+    pub async fn revoke(db: &DBConnection, id: i32) -> Result<(), AppError> {
+        let rows_affected = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL",
+            id
+        )
+        .execute(db)
+        .await?
+        .rows_affected();
+        if rows_affected == 0 {
+            return Err(AppError::NotFound("API key".into()));
+        }
+        Ok(())
+    }
+
+    /// key sent in `X-Api-Key: <key>` or `Authorization: ApiKey <key>`
+    pub fn from_headers(headers: &HeaderMap) -> Option<&str> {
+        if let Some(key) = headers.get("X-Api-Key") {
+            return key.to_str().ok();
+        }
+        headers
+            .get("Authorization")?
+            .to_str()
+            .ok()?
+            .strip_prefix("ApiKey ")
+    }
+
+    /// claim of the key's user limited to its scopes, `None` when the key is unknown, expired or revoked,
+    /// or its user isn't active.
+    /// every use is recorded in `last_used_at`.
+    pub async fn authenticate(db: &DBConnection, key: &str) -> Result<Option<UserClaim>, AppError> {
+        if !key.starts_with(KEY_PREFIX) {
+            return Ok(None);
+        }
+        let key = sqlx::query!(
+            r#"
+                UPDATE api_keys k SET last_used_at = NOW()
+                FROM users u
+                WHERE k.key_hash = $1
+                  AND u.id = k.user_id
+                  AND u.status = 'Active'
+                  AND k.revoked_at IS NULL
+                  AND (k.expires_at IS NULL OR k.expires_at > NOW())
+                RETURNING
+                    k.id, k.user_id, k.scopes, u.type AS "user_type: UserType",
+                    u.first_name, u.last_name, u.email, u.photo, u.locale AS "locale: Locale"
+            "#,
+            Token::hash(key)
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(key.map(|key| {
+            let mut claim = UserClaim::new(
+                key.user_id,
+                0,
+                key.user_type,
+                key.first_name,
+                key.last_name,
+                key.email,
+                key.photo,
+            )
+            .with_access(vec![], key.scopes);
+            claim.api_key_id = Some(key.id);
+            claim.locale = key.locale;
+            claim
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use chrono::{Duration, Utc};
+    use http::StatusCode;
+
+    use crate::db::DBConnection;
+    use crate::error::AppError;
+
+    use super::{ApiKey, NewApiKey};
+
+    async fn associate(pool: &DBConnection) -> i32 {
+        sqlx::query_scalar!(
+            r#"
+                INSERT INTO users (first_name, last_name, user_name, email, password, phone, type, state, country)
+                VALUES ('Sam', 'Rusty', 'sam', 'sam@hgicrusade.com', '-', '+17780000000', 'Associate', 'GA', 'US')
+                RETURNING id
+            "#
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    fn new_key(scopes: &[&str]) -> NewApiKey {
+        NewApiKey {
+            name: "reports".into(),
+            user_id: None,
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            expires_at: None,
+        }
+    }
+
+    #[sqlx::test(migrations = "../../migrations")]
+    async fn should_authenticate_until_revoked(pool: DBConnection) {
+        let user_id = associate(&pool).await;
+        let issued = new_key(&["ama.read"]).create(&pool, user_id).await.unwrap();
+        let claim = ApiKey::authenticate(&pool, &issued.key)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(claim.id, user_id);
+        assert_eq!(claim.api_key_id, Some(issued.id));
+        assert_eq!(claim.permissions, vec!["ama.read"]);
+        let keys = ApiKey::find(&pool).await.unwrap();
+        assert!(keys[0].last_used_at.is_some());
+
+        let wrong = format!("{}x", issued.key);
+        assert!(ApiKey::authenticate(&pool, &wrong).await.unwrap().is_none());
+        ApiKey::revoke(&pool, issued.id).await.unwrap();
+        let revoked = ApiKey::authenticate(&pool, &issued.key).await.unwrap();
+        assert!(revoked.is_none());
+        let key = ApiKey::find_by_id(&pool, issued.id).await.unwrap();
+        assert!(key.revoked_at.is_some());
+        assert!(ApiKey::revoke(&pool, issued.id).await.is_err());
+    }
+
+    #[sqlx::test(migrations = "../../migrations")]
+    async fn should_reject_expired_keys(pool: DBConnection) {
+        let user_id = associate(&pool).await;
+        let issued = new_key(&["ama.read"]).create(&pool, user_id).await.unwrap();
+        sqlx::query!(
+            "UPDATE api_keys SET expires_at = $1 WHERE id = $2",
+            (Utc::now() - Duration::minutes(1)).naive_utc(),
+            issued.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let expired = ApiKey::authenticate(&pool, &issued.key).await.unwrap();
+        assert!(expired.is_none());
+        let keys = ApiKey::find(&pool).await.unwrap();
+        assert!(keys[0].last_used_at.is_none());
+    }
+
+    #[sqlx::test(migrations = "../../migrations")]
+    async fn should_limit_scopes_to_user_permissions(pool: DBConnection) {
+        let user_id = associate(&pool).await;
+        let result = new_key(&["ama.read", "ama.write"])
+            .create(&pool, user_id)
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::FORBIDDEN))
+        ));
+        let result = new_key(&["nothing.here"]).create(&pool, user_id).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Response(_, StatusCode::BAD_REQUEST))
+        ));
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_static(value),
+        );
+        headers
+    }
+
+    #[test]
+    fn should_read_key_from_headers() {
+        let header = headers("x-api-key", "ck_1_secret");
+        assert_eq!(ApiKey::from_headers(&header), Some("ck_1_secret"));
+        let header = headers("authorization", "ApiKey ck_1_secret");
+        assert_eq!(ApiKey::from_headers(&header), Some("ck_1_secret"));
+        // bearer tokens are JWTs, not keys
+        let header = headers("authorization", "eyJhbGciOi");
+        assert_eq!(ApiKey::from_headers(&header), None);
+    }
+}
@@ -0,0 +1,141 @@
+use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use http::StatusCode;
+
+use crate::db::DBConnection;
+use crate::error::AppError;
+use crate::middleware::UserClaim;
+use crate::users::User;
+
+/// Extractor for the logged-in user, 401 when the request isn't authenticated.
+/// derefs to the `UserClaim`, use `user()` when the full `users` row is needed.
+pub struct AuthUser {
+    pub claim: UserClaim,
+    req: HttpRequest,
+}
+
+impl AuthUser {
+    fn from_claim(req: &HttpRequest) -> Option<Self> {
+        let claim = req.extensions().get::<UserClaim>().cloned()?;
+        Some(Self {
+            claim,
+            req: req.clone(),
+        })
+    }
+
+    /// row of the user, loaded once per request and shared with the other extractors
+    pub async fn user(&self) -> Result<Rc<User>, AppError> {
+        if let Some(user) = self.req.extensions().get::<Rc<User>>() {
+            return Ok(user.clone());
+        }
+        let db = self
+            .req
+            .app_data::<web::Data<DBConnection>>()
+            .ok_or_else(|| AppError::Message("Database connection is not configured".into()))?;
+        let user = Rc::new(User::find(db, self.claim.id).await?);
+        self.req.extensions_mut().insert(user.clone());
+        Ok(user)
+    }
+}
+
+impl Deref for AuthUser {
+    type Target = UserClaim;
+
+    fn deref(&self) -> &Self::Target {
+        &self.claim
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = Self::from_claim(req)
+            .ok_or_else(|| AppError::Response("login-required".into(), StatusCode::UNAUTHORIZED));
+        ready(user)
+    }
+}
+
+/// Extractor for public routes that behave differently for logged-in users, `None` for guests.
+pub struct OptionalAuthUser(pub Option<AuthUser>);
+
+impl FromRequest for OptionalAuthUser {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(OptionalAuthUser(AuthUser::from_claim(req))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use actix_web::test::TestRequest;
+    use actix_web::{web, FromRequest, HttpMessage, ResponseError};
+    use http::StatusCode;
+
+    use crate::db::DBConnection;
+    use crate::middleware::UserClaim;
+    use crate::users::{UserStatus, UserType};
+
+    use super::{AuthUser, OptionalAuthUser};
+
+    fn claim(id: i32) -> UserClaim {
+        UserClaim::new(
+            id,
+            1,
+            UserType::Associate,
+            "Hubert".into(),
+            "Humphrey".into(),
+            "hubert@hgicrusade.com".into(),
+            None,
+        )
+    }
+
+    #[actix_web::test]
+    async fn should_require_login() {
+        let req = TestRequest::default().to_http_request();
+        let result = AuthUser::extract(&req).await;
+        assert_eq!(
+            result.err().unwrap().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        let guest = OptionalAuthUser::extract(&req).await.unwrap();
+        assert!(guest.0.is_none());
+
+        req.extensions_mut().insert(claim(7));
+        assert_eq!(AuthUser::extract(&req).await.unwrap().id, 7);
+        let user = OptionalAuthUser::extract(&req).await.unwrap();
+        assert_eq!(user.0.unwrap().id, 7);
+    }
+
+    #[sqlx::test(migrations = "../../migrations")]
+    async fn should_load_user_once_per_request(pool: DBConnection) {
+        let id = sqlx::query_scalar!(
+            r#"
+                INSERT INTO users (first_name, last_name, user_name, email, password, phone, type, state, country)
+                VALUES ('Hubert', 'Humphrey', 'hubert', 'hubert@hgicrusade.com', '-', '+17786866393', 'Associate', 'GA', 'US')
+                RETURNING id
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let req = TestRequest::default()
+            .app_data(web::Data::new(pool))
+            .to_http_request();
+        req.extensions_mut().insert(claim(id));
+        let first = AuthUser::extract(&req).await.unwrap().user().await.unwrap();
+        assert_eq!(first.user_name, "hubert");
+        assert_eq!(first.status, UserStatus::Active);
+        let second = AuthUser::extract(&req).await.unwrap().user().await.unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+    }
+}
@@ -0,0 +1,104 @@
+use std::env::var;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use actix_web::HttpRequest;
+
+/// Proxies allowed to set `X-Forwarded-For`, `TRUSTED_PROXIES` is a comma separated list of ips,
+/// e.g. the load balancer. the header of any other peer is ignored since clients can send it.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    proxies: Vec<IpAddr>,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self { proxies }
+    }
+
+    pub fn from_env() -> &'static Self {
+        static PROXIES: OnceLock<TrustedProxies> = OnceLock::new();
+        PROXIES.get_or_init(|| {
+            let proxies = var("TRUSTED_PROXIES").unwrap_or_default();
+            Self::new(
+                proxies
+                    .split(',')
+                    .filter_map(|ip| ip.trim().parse().ok())
+                    .collect(),
+            )
+        })
+    }
+
+    /// address of the client, the peer unless it's a trusted proxy.
+    /// behind proxies it's the last `X-Forwarded-For` entry that isn't one of them,
+    /// the entries before it were added by the client and can't be trusted.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        if !self.proxies.contains(&peer) {
+            return Some(peer);
+        }
+        let forwarded: Vec<&str> = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .collect();
+        for ip in forwarded.into_iter().rev() {
+            match ip.trim().parse() {
+                Ok(ip) if self.proxies.contains(&ip) => continue,
+                Ok(ip) => return Some(ip),
+                Err(_) => break,
+            }
+        }
+        Some(peer)
+    }
+}
+
+/// client address of the request with the `TRUSTED_PROXIES` of the env, see `TrustedProxies`
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    TrustedProxies::from_env()
+        .client_ip(req)
+        .map(|ip| ip.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use actix_web::test::TestRequest;
+
+    use super::TrustedProxies;
+
+    fn request(peer: &str, forwarded: Option<&str>) -> actix_web::HttpRequest {
+        let mut req = TestRequest::get().peer_addr(SocketAddr::new(peer.parse().unwrap(), 4000));
+        if let Some(forwarded) = forwarded {
+            req = req.insert_header(("X-Forwarded-For", forwarded));
+        }
+        req.to_http_request()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn should_ignore_forwarded_header_of_untrusted_peer() {
+        let proxies = TrustedProxies::new(vec!["10.0.0.1".parse().unwrap()]);
+        let req = request("203.0.113.7", Some("198.51.100.1"));
+        assert_eq!(proxies.client_ip(&req), ip("203.0.113.7"));
+        assert_eq!(TrustedProxies::default().client_ip(&req), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn should_use_last_untrusted_forwarded_ip() {
+        let proxies = TrustedProxies::new(vec![
+            "10.0.0.1".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+        ]);
+        // the client made up the first entry
+        let req = request("10.0.0.1", Some("198.51.100.1, 203.0.113.7, 10.0.0.2"));
+        assert_eq!(proxies.client_ip(&req), ip("203.0.113.7"));
+        let req = request("10.0.0.1", None);
+        assert_eq!(proxies.client_ip(&req), ip("10.0.0.1"));
+    }
+}
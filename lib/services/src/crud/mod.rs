@@ -1 +1,2 @@
+pub mod policy;
 pub mod traits;
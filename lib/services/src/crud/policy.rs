@@ -0,0 +1,127 @@
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use http::StatusCode;
+use scooby::postgres::{Parameters, Select};
+use sqlx::postgres::PgArguments;
+use sqlx::Arguments;
+
+use crate::error::AppError;
+use crate::middleware::UserClaim;
+
+/// Resource whose rows belong to the user who created them.
+pub trait Owned {
+    const OWNER_COLUMN: &'static str = "created_by";
+}
+
+/// Row level access of the logged-in user, admins bypass ownership checks.
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    pub user_id: i32,
+    pub bypass: bool,
+}
+
+impl Policy {
+    pub fn for_user(user: &UserClaim) -> Self {
+        Self {
+            user_id: user.id,
+            bypass: user.is_admin(),
+        }
+    }
+
+    /// limit a list query to the rows owned by the user
+    pub fn scope<T: Owned>(
+        &self,
+        query: Select,
+        args: &mut PgArguments,
+        bind_count: &mut Parameters,
+        alias: &str,
+    ) -> Select {
+        if self.bypass {
+            return query;
+        }
+        args.add(self.user_id);
+        query.where_(format!(
+            "{alias}{} = {}",
+            T::OWNER_COLUMN,
+            bind_count.next()
+        ))
+    }
+}
+
+impl FromRequest for Policy {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let policy = match req.extensions().get::<UserClaim>() {
+            Some(user) => Ok(Self::for_user(user)),
+            None => Err(AppError::Response(
+                "login-required".into(),
+                StatusCode::UNAUTHORIZED,
+            )),
+        };
+        ready(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scooby::postgres::select;
+    use sqlx::postgres::PgArguments;
+
+    use struct_iterable::Iterable;
+
+    use crate::query_param::{OrderBy, QueryParams, StringFilter};
+
+    use super::*;
+
+    struct Note;
+
+    impl Owned for Note {}
+
+    #[derive(serde::Deserialize, serde::Serialize, Debug, Iterable)]
+    struct Columns {
+        content: StringFilter,
+        id: OrderBy,
+    }
+
+    fn params() -> QueryParams<Columns, Columns> {
+        QueryParams {
+            page: None,
+            limit: None,
+            filter: None,
+            filter_type: None,
+            meta: None,
+            order: None,
+        }
+    }
+
+    #[test]
+    fn should_scope_to_owner() {
+        let policy = Policy {
+            user_id: 7,
+            bypass: false,
+        };
+        let (query, _, _, _) = params()
+            .build_scoped_query::<Note>(select("*").from("notes"), "", 20, &policy)
+            .unwrap();
+        assert_eq!(
+            query.to_string(),
+            "SELECT * FROM notes WHERE created_by = $1 LIMIT 20"
+        );
+    }
+
+    #[test]
+    fn should_not_scope_admins() {
+        let policy = Policy {
+            user_id: 1,
+            bypass: true,
+        };
+        let mut args = PgArguments::default();
+        let mut bind_count = Parameters::new();
+        let query = policy.scope::<Note>(select("*").from("notes"), &mut args, &mut bind_count, "");
+        assert_eq!(query.to_string(), "SELECT * FROM notes");
+    }
+}
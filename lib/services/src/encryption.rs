@@ -1,6 +1,8 @@
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
+use sha2::{Digest, Sha256};
 
 use crate::error::AppError;
 
@@ -35,12 +37,29 @@ impl Jwt {
     }
 }
 
+/// Opaque random tokens for links sent to users, only the hash should be stored.
+pub struct Token;
+
+impl Token {
+    /// 32 random bytes, hex encoded
+    pub fn generate() -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        hex::encode(bytes)
+    }
+
+    /// sha256 of the token, hex encoded
+    pub fn hash(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use chrono::{Duration, Utc};
     use serde::{Deserialize, Serialize};
 
-    use crate::encryption::Jwt;
+    use crate::encryption::{Jwt, Token};
     use crate::load_env;
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -76,4 +95,13 @@ mod test {
         let decoded = Jwt::decode::<User>(&encoded.unwrap());
         assert_eq!(decoded.unwrap().code, "SAM".to_string());
     }
+
+    #[test]
+    fn should_hash_tokens_consistently() {
+        let token = Token::generate();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, Token::generate());
+        assert_eq!(Token::hash(&token), Token::hash(&token));
+        assert_ne!(Token::hash(&token), token);
+    }
 }
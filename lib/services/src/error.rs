@@ -1,13 +1,23 @@
-use std::error::Error;
-
+use actix_web::error::JsonPayloadError;
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::{error::ResponseError, HttpResponse};
 use actix_web_validator;
 use actix_web_validator::JsonConfig;
 use http::StatusCode;
 use serde::Serialize;
+use serde_json::error::Category;
+use serde_json::Map;
+use sqlx::error::ErrorKind;
+use sqlx::postgres::PgDatabaseError;
+use strum_macros::{AsRefStr, EnumIter};
 use thiserror::Error;
 
 use crate::db::DbErr;
+use crate::i18n::{t, t_args};
+use crate::logging::{current_path, RequestId};
+use crate::validation::{field_errors, FieldError, FieldErrors};
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
 
 pub type ErrorMessage = String;
 pub type Cause = String;
@@ -38,50 +48,444 @@ pub enum AppError {
 
     #[error("{0}")]
     JsonParsingError(#[from] serde_json::Error),
+
+    /// request body that couldn't be read, parsed or validated
+    #[error("{1}")]
+    Payload(ErrorCode, ErrorMessage),
+
+    /// failures of each field, sent as the `errors` of the response
+    #[error("Validation failed")]
+    Validation(FieldErrors),
+}
+
+impl From<validator::ValidationErrors> for AppError {
+    fn from(errors: validator::ValidationErrors) -> Self {
+        Self::Validation(field_errors(&errors))
+    }
+}
+
+/// Stable, machine-readable `code` of error responses, clients should rely on it
+/// rather than the `detail` message. codes are only ever added, never renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsRefStr, EnumIter)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    MalformedJson,
+    InvalidPayload,
+    ValidationFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    AlreadyExists,
+    ReferenceNotFound,
+    StillReferenced,
+    ConstraintViolation,
+    RateLimited,
+    TryAgain,
+    ClientError,
+    DatabaseError,
+    ConfigurationError,
+    InternalError,
+}
+
+impl ErrorCode {
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => Self::BadRequest,
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::FORBIDDEN => Self::Forbidden,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::CONFLICT => Self::Conflict,
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => Self::UnsupportedMediaType,
+            StatusCode::UNPROCESSABLE_ENTITY => Self::ValidationFailed,
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            StatusCode::SERVICE_UNAVAILABLE => Self::TryAgain,
+            status if status.is_client_error() => Self::ClientError,
+            _ => Self::InternalError,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest | Self::MalformedJson => StatusCode::BAD_REQUEST,
+            Self::InvalidPayload
+            | Self::ValidationFailed
+            | Self::ReferenceNotFound
+            | Self::ConstraintViolation => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict | Self::AlreadyExists | Self::StillReferenced => StatusCode::CONFLICT,
+            Self::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            Self::TryAgain => StatusCode::SERVICE_UNAVAILABLE,
+            Self::ClientError => StatusCode::BAD_REQUEST,
+            Self::DatabaseError | Self::ConfigurationError | Self::InternalError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: String,
+/// RFC 7807 body of the error responses, with the `code` and `request_id` extensions.
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_: &'static str,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub code: ErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<FieldErrors>,
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::DbError(e) => db_failure(e).map_or(ErrorCode::DatabaseError, |f| f.code),
+            Self::EnvVarError(_) => ErrorCode::ConfigurationError,
+            Self::JsonParsingError(_) | Self::Message(_) | Self::MessageWithCause(_, _) => {
+                ErrorCode::InternalError
+            }
+            Self::Response(_, status) | Self::ResponseWithCause(_, status, _) => {
+                ErrorCode::from_status(*status)
+            }
+            Self::Payload(code, _) => *code,
+            Self::Validation(_) => ErrorCode::ValidationFailed,
+        }
+    }
+
+    /// server errors only say that something went wrong, the cause is logged and reported instead
+    pub fn problem(&self) -> Problem {
+        let status = self.status_code();
+        let failure = match self {
+            Self::DbError(e) => db_failure(e),
+            _ => None,
+        };
+        // messages that are catalogue ids are translated, see `i18n::t`
+        let detail = match (self, &failure) {
+            (_, Some(failure)) => failure.detail.clone(),
+            (Self::NotFound(entity), _) => t_args("not-found", &[("entity", entity.clone())]),
+            (Self::Validation(_), _) => t("validation-failed"),
+            _ if status.is_server_error() => t("unexpected-error"),
+            _ => t(&self.to_string()),
+        };
+        let title_id = format!("title-{}", status.as_u16());
+        let title = match t(&title_id) {
+            title if title == title_id => status.canonical_reason().unwrap_or("Error").into(),
+            title => title,
+        };
+        Problem {
+            type_: "about:blank",
+            title,
+            status: status.as_u16(),
+            detail,
+            instance: current_path(),
+            code: self.code(),
+            request_id: RequestId::current().map(|id| id.0),
+            errors: match (self, failure) {
+                (Self::Validation(fields), _) => Some(fields.clone()),
+                (
+                    _,
+                    Some(DbFailure {
+                        field: Some((field, error)),
+                        ..
+                    }),
+                ) => Some(FieldErrors::from([(field, vec![error])])),
+                _ => None,
+            },
+        }
+    }
 }
 
 impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::NotFound(_) => StatusCode::NOT_FOUND,
-            Self::DbError(_)
-            | Self::JsonParsingError(_)
-            | Self::EnvVarError(_)
-            | Self::Message(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::DbError(e) => {
+                db_failure(e).map_or(StatusCode::INTERNAL_SERVER_ERROR, |f| f.code.status())
+            }
+            Self::JsonParsingError(_) | Self::EnvVarError(_) | Self::Message(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
             Self::MessageWithCause(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Response(_, code) => code.to_owned(),
             Self::ResponseWithCause(_, code, _) => code.to_owned(),
+            Self::Payload(code, _) => code.status(),
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        let status_code = self.status_code();
-        let error_response = ErrorResponse {
-            error: self.to_string(),
-        };
-        HttpResponse::build(status_code).json(error_response)
+        let status = self.status_code();
+        let mut res = HttpResponse::build(status);
+        if let Self::DbError(e) = self {
+            // clients only get the meaning of the error, the message may have table names or values
+            if status.is_server_error() {
+                tracing::error!("{e} - database error");
+            } else {
+                tracing::warn!("{e} - database error");
+            }
+        }
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            res.insert_header((RETRY_AFTER, 1));
+        }
+        res.content_type(PROBLEM_CONTENT_TYPE).json(self.problem())
     }
 }
 
+/// What a database error means for the client, `None` when it's a bug or an outage.
+struct DbFailure {
+    code: ErrorCode,
+    detail: String,
+    field: Option<(String, FieldError)>,
+}
+
+fn db_failure(e: &DbErr) -> Option<DbFailure> {
+    let failure = |code, detail: &str| DbFailure {
+        code,
+        detail: t(detail),
+        field: None,
+    };
+    let error = match e {
+        DbErr::RowNotFound => return Some(failure(ErrorCode::NotFound, "record-not-found")),
+        DbErr::PoolTimedOut => return Some(failure(ErrorCode::TryAgain, "try-again")),
+        DbErr::Database(error) => error,
+        _ => return None,
+    };
+    // serialization failure and deadlock, the transaction can be retried as is
+    if matches!(error.code().as_deref(), Some("40001" | "40P01")) {
+        return Some(failure(ErrorCode::TryAgain, "try-again"));
+    }
+    let pg = error.try_downcast_ref::<PgDatabaseError>();
+    let detail = pg.and_then(|pg| pg.detail()).unwrap_or_default();
+    // "Key (email)=(sam@hgicrusade.com) already exists.", the value stays out of the response
+    let key = detail
+        .strip_prefix("Key (")
+        .and_then(|key| key.split_once(")="))
+        .map(|(columns, _)| columns.to_string());
+    let with_field = |mut failure: DbFailure, field: Option<String>, code: &str| {
+        failure.field = field.map(|field| {
+            let error = FieldError {
+                code: code.into(),
+                message: t(&format!("validation-{code}")),
+                params: Map::new(),
+            };
+            (field, error)
+        });
+        failure
+    };
+    Some(match error.kind() {
+        ErrorKind::UniqueViolation => {
+            let mut failure = failure(ErrorCode::AlreadyExists, "record-already-exists");
+            if let Some(key) = &key {
+                failure.detail = t_args("field-already-exists", &[("field", key.clone())]);
+            }
+            with_field(failure, key, "unique")
+        }
+        ErrorKind::ForeignKeyViolation if detail.contains("is not present") => {
+            let failure = failure(ErrorCode::ReferenceNotFound, "reference-not-found");
+            with_field(failure, key, "exists")
+        }
+        ErrorKind::ForeignKeyViolation => failure(ErrorCode::StillReferenced, "still-referenced"),
+        ErrorKind::NotNullViolation => {
+            let failure = failure(ErrorCode::ValidationFailed, "validation-failed");
+            let column = pg.and_then(|pg| pg.column()).map(Into::into);
+            with_field(failure, column, "required")
+        }
+        ErrorKind::CheckViolation => failure(ErrorCode::ConstraintViolation, "invalid-value"),
+        _ => return None,
+    })
+}
+
+/// bodies that aren't JSON are a 400, JSON that doesn't fit the form or fails validation a 422
 pub fn actix_error_handler() -> JsonConfig {
-    JsonConfig::default().limit(4096).error_handler(|err, _| {
-        // Payload error: Json deserialize error:
-        let err = err.source();
-        let mut error_message = "".to_string();
-        if let Some(error) = err {
-            error_message = error
-                .to_string()
-                .replace("Json deserialize error: ", "")
-                .split("at line ")
-                .next()
-                .unwrap_or("")
-                .to_string()
+    JsonConfig::default()
+        .limit(4096)
+        .error_handler(|err, _| payload_error(err).into())
+}
+
+fn payload_error(err: actix_web_validator::Error) -> AppError {
+    use actix_web_validator::error::{DeserializeErrors, Error};
+    let json = |e: serde_json::Error| {
+        let code = match e.classify() {
+            Category::Data => ErrorCode::InvalidPayload,
+            _ => ErrorCode::MalformedJson,
         };
-        AppError::Response(error_message, StatusCode::CONFLICT).into()
-    })
+        // drop the position, e.g. "missing field `email` at line 1 column 2"
+        let message = e.to_string();
+        let message = message.split(" at line ").next().unwrap_or_default();
+        AppError::Payload(code, message.into())
+    };
+    match err {
+        Error::Validate(e) => e.into(),
+        Error::Deserialize(DeserializeErrors::DeserializeJson(e)) => json(e),
+        Error::JsonPayloadError(JsonPayloadError::Deserialize(e)) => json(e),
+        Error::JsonPayloadError(
+            e @ (JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. }),
+        ) => AppError::Payload(ErrorCode::PayloadTooLarge, e.to_string()),
+        Error::JsonPayloadError(JsonPayloadError::ContentType) => {
+            AppError::Payload(ErrorCode::UnsupportedMediaType, "content-type-json".into())
+        }
+        e => AppError::Payload(ErrorCode::BadRequest, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App, HttpResponse, ResponseError};
+    use http::StatusCode;
+    use serde::Deserialize;
+    use serde_json::Value;
+    use sqlx::Executor;
+    use validator::Validate;
+
+    use crate::db::DBConnection;
+    use crate::logging::{RequestLogger, REQUEST_ID_HEADER};
+
+    use super::{actix_error_handler, AppError, ErrorCode, PROBLEM_CONTENT_TYPE};
+
+    #[derive(Deserialize, Validate)]
+    struct Form {
+        #[validate(email)]
+        email: String,
+    }
+
+    async fn problem(body: &str) -> (StatusCode, Value) {
+        let app = init_service(
+            App::new()
+                .app_data(actix_error_handler())
+                .wrap(RequestLogger)
+                .route(
+                    "/form",
+                    web::post().to(|form: actix_web_validator::Json<Form>| async move {
+                        HttpResponse::Ok().body(form.into_inner().email)
+                    }),
+                )
+                .route(
+                    "/missing",
+                    web::get()
+                        .to(|| async { Err::<HttpResponse, _>(AppError::NotFound("User".into())) }),
+                )
+                .route(
+                    "/fail",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(AppError::Message("password=secret".into()))
+                    }),
+                ),
+        )
+        .await;
+        let req = match body {
+            "/missing" | "/fail" => TestRequest::get().uri(body),
+            body => TestRequest::post()
+                .uri("/form")
+                .insert_header(("content-type", "application/json"))
+                .set_payload(body.to_string()),
+        };
+        let req = req
+            .insert_header((REQUEST_ID_HEADER, "abc-123"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            PROBLEM_CONTENT_TYPE
+        );
+        (res.status(), read_body_json(res).await)
+    }
+
+    #[actix_web::test]
+    async fn should_respond_with_problem_details() {
+        let (status, body) = problem("/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["type"], "about:blank");
+        assert_eq!(body["title"], "Not Found");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["detail"], "User Not Found");
+        assert_eq!(body["instance"], "/missing");
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["request_id"], "abc-123");
+
+        let (status, body) = problem("/fail").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "internal_error");
+        assert_eq!(body["detail"], "An unexpected error occurred");
+    }
+
+    #[actix_web::test]
+    async fn should_separate_malformed_and_invalid_payloads() {
+        let (status, body) = problem("{\"email\":").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "malformed_json");
+
+        let (status, body) = problem("{}").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "invalid_payload");
+        assert_eq!(body["detail"], "missing field `email`");
+
+        let (status, body) = problem("{\"email\":\"nope\"}").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation_failed");
+        assert_eq!(body["errors"]["email"][0]["code"], "email");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn should_translate_database_errors(pool: DBConnection) {
+        pool.execute(
+            r#"
+                CREATE TABLE teams (id INT PRIMARY KEY, name TEXT NOT NULL UNIQUE CHECK (name <> ''));
+                CREATE TABLE members (team_id INT NOT NULL REFERENCES teams (id));
+                INSERT INTO teams VALUES (1, 'red');
+                INSERT INTO members VALUES (1);
+            "#,
+        )
+        .await
+        .unwrap();
+        let failed = |sql: &'static str| {
+            let pool = pool.clone();
+            async move { AppError::from(sqlx::query(sql).execute(&pool).await.unwrap_err()) }
+        };
+
+        let error = failed("INSERT INTO teams VALUES (2, 'red')").await;
+        let problem = error.problem();
+        assert_eq!(error.status_code(), StatusCode::CONFLICT);
+        assert_eq!(problem.code, ErrorCode::AlreadyExists);
+        assert_eq!(problem.detail, "name already exists");
+        assert_eq!(problem.errors.unwrap()["name"][0].code, "unique");
+
+        let error = failed("INSERT INTO members VALUES (9)").await;
+        assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.problem().errors.unwrap()["team_id"][0].code, "exists");
+        let error = failed("DELETE FROM teams").await;
+        assert_eq!(error.code(), ErrorCode::StillReferenced);
+        let error = failed("INSERT INTO members VALUES (NULL)").await;
+        assert_eq!(
+            error.problem().errors.unwrap()["team_id"][0].code,
+            "required"
+        );
+        let error = failed("INSERT INTO teams VALUES (3, '')").await;
+        assert_eq!(error.code(), ErrorCode::ConstraintViolation);
+
+        let missing = sqlx::query("SELECT 1 FROM teams WHERE id = 9")
+            .fetch_one(&pool)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(AppError::from(missing).status_code(), StatusCode::NOT_FOUND);
+        let broken = failed("SELECT * FROM nowhere").await;
+        assert_eq!(broken.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(broken.problem().detail, "An unexpected error occurred");
+    }
 }
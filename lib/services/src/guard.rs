@@ -1,15 +1,184 @@
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+
+use actix_web::dev::Payload;
 use actix_web::guard::{fn_guard, Guard as ActixGuard};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use http::StatusCode;
 
+use crate::error::AppError;
+use crate::i18n::t_args;
 use crate::middleware::UserClaim;
 
+/// Named permission stored in the `permissions` table.
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+macro_rules! permission {
+    ($type:ident, $name:literal) => {
+        pub struct $type;
+
+        impl Permission for $type {
+            const NAME: &'static str = $name;
+        }
+    };
+}
+
+permission!(AmaRead, "ama.read");
+permission!(AmaWrite, "ama.write");
+permission!(ApiKeyManage, "api_keys.manage");
+permission!(UserImpersonate, "users.impersonate");
+permission!(UserManage, "users.manage");
+
 pub struct Guard;
 
 impl Guard {
-    pub fn has_access(_name: String) -> impl ActixGuard + Sized {
-        fn_guard(|ctx| {
+    /// route guard, the route only matches when the user has the permission.
+    /// use `Authorized` in the handler instead to respond with 403.
+    pub fn has_access(name: String) -> impl ActixGuard + Sized {
+        fn_guard(move |ctx| {
             let ext = ctx.req_data();
-            let _user = ext.get::<UserClaim>();
-            true
+            ext.get::<UserClaim>().is_some_and(|user| user.can(&name))
         })
     }
+
+    /// claim of the logged-in user if it has the permission, 401 without a user and 403 without the permission.
+    pub fn authorize(req: &HttpRequest, name: &str) -> Result<UserClaim, AppError> {
+        let ext = req.extensions();
+        let Some(user) = ext.get::<UserClaim>() else {
+            return Err(AppError::Response(
+                "login-required".into(),
+                StatusCode::UNAUTHORIZED,
+            ));
+        };
+        if !user.can(name) {
+            return Err(AppError::Response(
+                t_args("permission-required", &[("name", name.into())]),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+        Ok(user.clone())
+    }
+}
+
+/// Extractor for handlers that need permission `P`, e.g. `user: Authorized<AmaWrite>`.
+pub struct Authorized<P: Permission>(pub UserClaim, PhantomData<P>);
+
+impl<P: Permission> FromRequest for Authorized<P> {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Guard::authorize(req, P::NAME).map(|user| Authorized(user, PhantomData)))
+    }
+}
+
+/// Extractor for sensitive actions on the account, e.g. password changes and payments,
+/// that only the user can take: not an admin signed in as them or an API key acting as them.
+pub struct AccountHolder(pub UserClaim);
+
+impl FromRequest for AccountHolder {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let ext = req.extensions();
+        let result = match ext.get::<UserClaim>() {
+            None => Err(AppError::Response(
+                "login-required".into(),
+                StatusCode::UNAUTHORIZED,
+            )),
+            Some(user) if user.impersonated_by.is_some() => Err(AppError::Response(
+                "impersonation-not-allowed".into(),
+                StatusCode::FORBIDDEN,
+            )),
+            Some(user) if user.api_key_id.is_some() => Err(AppError::Response(
+                "api-key-not-allowed".into(),
+                StatusCode::FORBIDDEN,
+            )),
+            Some(user) => Ok(AccountHolder(user.clone())),
+        };
+        ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use actix_web::{FromRequest, HttpMessage, ResponseError};
+    use http::StatusCode;
+
+    use crate::middleware::UserClaim;
+    use crate::users::UserType;
+
+    use super::{AccountHolder, AmaRead, AmaWrite, Authorized};
+
+    fn claim(user_type: UserType, permissions: Vec<String>) -> UserClaim {
+        UserClaim::new(
+            1,
+            1,
+            user_type,
+            "Sam".into(),
+            "Rusty".into(),
+            "sam@hgicrusade.com".into(),
+            None,
+        )
+        .with_access(vec![user_type.as_ref().to_string()], permissions)
+    }
+
+    #[actix_web::test]
+    async fn should_check_permissions() {
+        let req = TestRequest::default().to_http_request();
+        let result = Authorized::<AmaRead>::extract(&req).await;
+        assert_eq!(
+            result.err().unwrap().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        req.extensions_mut()
+            .insert(claim(UserType::Associate, vec!["ama.read".into()]));
+        assert!(Authorized::<AmaRead>::extract(&req).await.is_ok());
+        let result = Authorized::<AmaWrite>::extract(&req).await;
+        assert_eq!(result.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn should_allow_admins_everything() {
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(claim(UserType::Admin, vec![]));
+        assert!(Authorized::<AmaWrite>::extract(&req).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn should_limit_api_keys_to_scopes() {
+        let req = TestRequest::default().to_http_request();
+        let mut key = claim(UserType::Admin, vec!["ama.read".into()]);
+        key.api_key_id = Some(1);
+        req.extensions_mut().insert(key);
+        assert!(Authorized::<AmaRead>::extract(&req).await.is_ok());
+        let result = Authorized::<AmaWrite>::extract(&req).await;
+        assert_eq!(result.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn should_block_sensitive_actions_while_impersonating() {
+        let req = TestRequest::default().to_http_request();
+        let mut user = claim(UserType::Associate, vec!["ama.read".into()]);
+        user.impersonated_by = Some(2);
+        req.extensions_mut().insert(user);
+        assert!(Authorized::<AmaRead>::extract(&req).await.is_ok());
+        let result = AccountHolder::extract(&req).await;
+        assert_eq!(result.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn should_block_sensitive_actions_with_api_keys() {
+        let req = TestRequest::default().to_http_request();
+        let mut key = claim(UserType::Associate, vec!["ama.read".into()]);
+        key.api_key_id = Some(1);
+        req.extensions_mut().insert(key);
+        let result = AccountHolder::extract(&req).await;
+        assert_eq!(result.err().unwrap().status_code(), StatusCode::FORBIDDEN);
+    }
 }
@@ -0,0 +1,266 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderValue, ACCEPT_LANGUAGE, CONTENT_LANGUAGE};
+use serde::{Deserialize, Serialize};
+use strum_macros::AsRefStr;
+
+tokio::task_local! {
+    static CURRENT_LOCALE: Cell<Locale>;
+}
+
+/// Languages with a message catalogue in `locales/`, stored in `users.locale`.
+#[derive(
+    Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, Default, Eq, PartialEq, Hash, AsRefStr,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+}
+
+impl Locale {
+    /// `fr-CA` and `fr` are both French
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?;
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Some(Self::En),
+            "fr" => Some(Self::Fr),
+            _ => None,
+        }
+    }
+
+    /// supported language with the highest weight in an `Accept-Language` header, e.g. `fr-CA,fr;q=0.9,en;q=0.8`
+    pub fn negotiate(accept_language: &str) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+        for range in accept_language.split(',') {
+            let mut parts = range.split(';');
+            let Some(locale) = parts.next().and_then(Self::from_tag) else {
+                continue;
+            };
+            let weight = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            if weight > 0.0 && best.is_none_or(|(_, best)| weight > best) {
+                best = Some((locale, weight));
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+
+    /// language of the request being handled, English outside of a request
+    pub fn current() -> Self {
+        CURRENT_LOCALE.try_with(Cell::get).unwrap_or_default()
+    }
+
+    /// use `self` for the rest of the request, e.g. the language set in the user's profile
+    pub fn prefer(self) {
+        let _ = CURRENT_LOCALE.try_with(|locale| locale.set(self));
+    }
+
+    /// run `f` with `self` as the current locale
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT_LOCALE.scope(Cell::new(self), f).await
+    }
+
+    fn catalogue(&self) -> &'static HashMap<&'static str, &'static str> {
+        static EN: OnceLock<HashMap<&str, &str>> = OnceLock::new();
+        static FR: OnceLock<HashMap<&str, &str>> = OnceLock::new();
+        match self {
+            Self::En => EN.get_or_init(|| parse(include_str!("../locales/en.ftl"))),
+            Self::Fr => FR.get_or_init(|| parse(include_str!("../locales/fr.ftl"))),
+        }
+    }
+}
+
+/// Messages of a catalogue, a subset of the Fluent syntax: `id = text with { $arg }`,
+/// one message per line and `#` starts a comment.
+fn parse(source: &'static str) -> HashMap<&'static str, &'static str> {
+    let mut messages = HashMap::new();
+    for line in source.lines() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if let Some((id, text)) = line.split_once(" = ") {
+            messages.insert(id.trim(), text.trim());
+        }
+    }
+    messages
+}
+
+/// message `id` in the current locale, English when it isn't translated.
+/// anything that isn't a message id is returned as is, so literal messages still work.
+pub fn t(id: &str) -> String {
+    t_args(id, &[])
+}
+
+/// `t` with the `{ $name }` placeholders replaced by `args`
+pub fn t_args(id: &str, args: &[(&str, String)]) -> String {
+    let text = Locale::current()
+        .catalogue()
+        .get(id)
+        .or_else(|| Locale::En.catalogue().get(id));
+    let Some(text) = text else {
+        return id.to_string();
+    };
+    args.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{ ${name} }}"), value)
+    })
+}
+
+/// `Accept-Language` picks the locale of the request, the claim's locale overrides it once logged in,
+/// see `middleware::Authentication`. responses get the `Content-Language` header.
+pub struct Localization;
+
+impl<S, B> Transform<S, ServiceRequest> for Localization
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = LocalizationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LocalizationMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct LocalizationMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for LocalizationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let locale = req
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(Locale::negotiate)
+            .unwrap_or_default();
+        Box::pin(locale.scope(async move {
+            let mut res = service.call(req).await?;
+            if let Ok(value) = HeaderValue::from_str(Locale::current().as_ref()) {
+                res.headers_mut().insert(CONTENT_LANGUAGE, value);
+            }
+            Ok(res)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use http::StatusCode;
+    use serde_json::Value;
+
+    use strum::IntoEnumIterator;
+
+    use crate::error::{AppError, ErrorCode};
+    use crate::validation::VALIDATION_CODES;
+
+    use super::{t, t_args, Locale, Localization};
+
+    #[test]
+    fn should_negotiate_supported_locales() {
+        assert_eq!(
+            Locale::negotiate("fr-CA,fr;q=0.9,en;q=0.8"),
+            Some(Locale::Fr)
+        );
+        assert_eq!(
+            Locale::negotiate("de-DE, en;q=0.5, fr;q=0.7"),
+            Some(Locale::Fr)
+        );
+        assert_eq!(Locale::negotiate("en-US,fr;q=0"), Some(Locale::En));
+        assert_eq!(Locale::negotiate("de, *;q=0.1"), None);
+    }
+
+    #[actix_web::test]
+    async fn should_translate_in_current_locale() {
+        assert_eq!(t("validation-email"), "Must be a valid email");
+        let french =
+            Locale::Fr.scope(async { t_args("password-history", &[("count", "5".into())]) });
+        assert_eq!(
+            french.await,
+            "Le mot de passe ne peut pas être l'un de vos 5 derniers"
+        );
+        assert_eq!(t("Not a message id"), "Not a message id");
+    }
+
+    #[test]
+    fn should_translate_every_message() {
+        let ids = |locale: Locale| locale.catalogue().keys().copied().collect::<HashSet<_>>();
+        assert_eq!(ids(Locale::En), ids(Locale::Fr));
+    }
+
+    #[test]
+    fn should_resolve_built_ids_in_every_locale() {
+        let mut ids: Vec<String> = ErrorCode::iter()
+            .map(|code| format!("title-{}", code.status().as_u16()))
+            .collect();
+        ids.extend(VALIDATION_CODES.map(|code| format!("validation-{code}")));
+        ids.push("validation-invalid".into());
+        for locale in [Locale::En, Locale::Fr] {
+            for id in &ids {
+                assert!(
+                    locale.catalogue().contains_key(id.as_str()),
+                    "{id} is missing in {}",
+                    locale.as_ref()
+                );
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn should_respond_in_negotiated_locale() {
+        let app = init_service(App::new().wrap(Localization).route(
+            "/",
+            web::get().to(|| async {
+                let error = AppError::Response("login-required".into(), StatusCode::UNAUTHORIZED);
+                Err::<HttpResponse, _>(error)
+            }),
+        ))
+        .await;
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header(("Accept-Language", "fr-CA,en;q=0.8"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.headers().get("content-language").unwrap(), "fr");
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body["title"], "Non authentifié");
+        assert_eq!(body["detail"], "Connexion requise");
+
+        let res = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(res.headers().get("content-language").unwrap(), "en");
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body["detail"], "Login required");
+    }
+}
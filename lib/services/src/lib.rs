@@ -2,19 +2,31 @@ use std::env::var;
 
 use dotenvy::dotenv;
 use serde::{Deserialize, Serialize};
+use strum_macros::AsRefStr;
 
+pub mod api_key;
+pub mod auth_user;
+pub mod client_ip;
 pub mod crud;
 pub mod db;
 pub mod encryption;
 pub mod error;
 pub mod guard;
+pub mod i18n;
+pub mod logging;
 pub mod middleware;
+pub mod password;
+pub mod public_routes;
 pub mod query_param;
 pub mod queue;
+pub mod rate_limit;
+pub mod reporting;
 pub mod response;
+pub mod session;
 pub mod users;
+pub mod validation;
 
-#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, Eq, PartialEq, Hash, AsRefStr)]
 pub enum Country {
     CA,
     US,
@@ -36,3 +48,11 @@ pub fn load_env(required_vars: Option<Vec<&str>>) {
         });
     }
 }
+
+/// env var parsed as `T`, `default` when it's missing or invalid
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
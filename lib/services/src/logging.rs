@@ -0,0 +1,219 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use rand::RngCore;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use crate::middleware::UserClaim;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+    static CURRENT_PATH: String;
+}
+
+/// JSON logs on stdout, every line has the fields of the request span, e.g. `request_id`.
+/// `RUST_LOG_LEVEL` sets the level, `info` by default.
+pub fn init() {
+    let level = std::env::var("RUST_LOG_LEVEL")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(tracing::Level::INFO);
+    tracing_subscriber::fmt()
+        .json()
+        .with_max_level(level)
+        .with_current_span(true)
+        .with_span_list(false)
+        .with_target(false)
+        .init();
+}
+
+/// path of the request being handled, e.g. the `instance` of error responses
+pub fn current_path() -> Option<String> {
+    CURRENT_PATH.try_with(|path| path.clone()).ok()
+}
+
+/// Id of the request, from the `X-Request-Id` header or generated, also extracted in handlers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// ids sent by clients are only kept when they are short and safe to log
+    fn from_header(req: &ServiceRequest) -> Option<Self> {
+        let id = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+        let valid = !id.is_empty()
+            && id.len() <= 128
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        valid.then(|| Self(id.into()))
+    }
+
+    fn generate() -> Self {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(hex::encode(bytes))
+    }
+
+    /// id of the request being handled, `None` outside of a request, e.g. in the queue handler
+    pub fn current() -> Option<Self> {
+        CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+    }
+
+    /// run `f` as part of the request, so `current` and queued messages get its id
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT_REQUEST_ID.scope(self, f).await
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let id = req
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(RequestId::generate);
+        ready(Ok(id))
+    }
+}
+
+/// Logs every request in a span with its id, method, path, status, latency and user id.
+/// the id is sent back in the `X-Request-Id` header, wrap it last so it covers the other middlewares.
+pub struct RequestLogger;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestLogger
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestLoggerMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLoggerMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestLoggerMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLoggerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let id = RequestId::from_header(&req).unwrap_or_else(RequestId::generate);
+        req.extensions_mut().insert(id.clone());
+        let span = tracing::info_span!(
+            "request",
+            request_id = %id.0,
+            method = %req.method(),
+            path = %req.path(),
+            status = Empty,
+            latency_ms = Empty,
+            user_id = Empty,
+        );
+        let started_at = Instant::now();
+        let scope = id.clone();
+        let path = req.path().to_string();
+        let handle = async move {
+            let result = service.call(req).await;
+            let span = tracing::Span::current();
+            span.record("latency_ms", started_at.elapsed().as_millis() as u64);
+            // errors of the inner middlewares are turned into responses by actix after this
+            let mut res = match result {
+                Ok(res) => res,
+                Err(e) => {
+                    span.record("status", e.as_response_error().status_code().as_u16());
+                    tracing::error!("{e} - request failed");
+                    return Err(e);
+                }
+            };
+            span.record("status", res.status().as_u16());
+            if let Some(user) = res.request().extensions().get::<UserClaim>() {
+                span.record("user_id", user.id);
+            }
+            if res.status().is_server_error() {
+                tracing::error!("request failed");
+            } else {
+                tracing::info!("request completed");
+            }
+            if let Ok(value) = HeaderValue::from_str(&id.0) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        };
+        Box::pin(
+            scope
+                .scope(CURRENT_PATH.scope(path, handle))
+                .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    use crate::queue::{Message, MessageType};
+
+    use super::{RequestLogger, REQUEST_ID_HEADER};
+
+    #[actix_web::test]
+    async fn should_propagate_request_id_to_messages() {
+        let app = init_service(App::new().wrap(RequestLogger).route(
+            "/",
+            web::get().to(|| async {
+                let message = Message::new(MessageType::Message, "{}".into());
+                HttpResponse::Ok().body(message.request_id.unwrap_or_default())
+            }),
+        ))
+        .await;
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "abc-123"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+        assert_eq!(read_body(res).await, "abc-123");
+
+        // unsafe ids are replaced
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "a b;drop"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        let id = res.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert_eq!(id.len(), 32);
+        assert_eq!(read_body(res).await, id.as_bytes());
+        assert!(Message::new(MessageType::Message, "{}".into())
+            .request_id
+            .is_none());
+    }
+}
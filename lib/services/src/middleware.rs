@@ -1,52 +1,208 @@
-use actix_web::dev::ServiceRequest;
-use actix_web::HttpMessage;
-use chrono::{Duration, Utc};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, HttpMessage};
+use chrono::{Duration, NaiveDateTime, Utc};
+use http::StatusCode;
 use serde::{Deserialize, Serialize};
 
-const NO_LOGIN_REQUIRED: [&str; 2] = ["/", "/authorization"];
+use crate::api_key::ApiKey;
+use crate::db::DBConnection;
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::public_routes::PublicRoutes;
+use crate::session::Session;
+use crate::users::UserType;
+
+const IMPERSONATED_BY_HEADER: &str = "x-impersonated-by";
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UserClaim {
+    /// user id
+    pub id: i32,
+    /// session id, see `session::Session`
+    pub sid: i32,
+    pub user_type: UserType,
     pub first_name: String,
     pub last_name: String,
     pub email: String,
     pub photo: Option<String>,
+    /// role names and the permissions they grant, as they were when the token was issued
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+    /// set when the request is authenticated with an API key, see `api_key::ApiKey`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<i32>,
+    /// id of the admin signed in as this user, see `authorization::impersonate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<i32>,
+    /// language set in the user's profile, the browser's is used otherwise, see `i18n::Localization`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
     pub exp: i64,
 }
 
 impl UserClaim {
     pub fn new(
+        id: i32,
+        sid: i32,
+        user_type: UserType,
         first_name: String,
         last_name: String,
         email: String,
         photo: Option<String>,
     ) -> Self {
         Self {
+            id,
+            sid,
+            user_type,
             first_name,
             last_name,
             email,
             photo,
-            exp: (Utc::now() + Duration::hours(24)).timestamp(),
+            roles: vec![],
+            permissions: vec![],
+            api_key_id: None,
+            impersonated_by: None,
+            locale: None,
+            exp: Self::expires_at().timestamp(),
         }
     }
+
+    pub fn with_access(mut self, roles: Vec<String>, permissions: Vec<String>) -> Self {
+        self.roles = roles;
+        self.permissions = permissions;
+        self
+    }
+
+    /// API keys of admins are still limited to their scopes
+    pub fn is_admin(&self) -> bool {
+        self.user_type == UserType::Admin && self.api_key_id.is_none()
+    }
+
+    /// admins have every permission
+    pub fn can(&self, permission: &str) -> bool {
+        self.is_admin() || self.permissions.iter().any(|p| p == permission)
+    }
+
+    /// tokens and their sessions are valid for 24 hours
+    pub fn expires_at() -> NaiveDateTime {
+        (Utc::now() + Duration::hours(24)).naive_utc()
+    }
 }
 
 pub struct Middleware;
 
 impl Middleware {
-    pub fn check_login(req: &ServiceRequest) -> bool {
-        let path = req.path();
-        if NO_LOGIN_REQUIRED.iter().any(|e| e.starts_with(path)) {
+    /// verify the token and that its session hasn't been revoked, or the API key,
+    /// the claim is added to request extensions.
+    pub async fn check_login(req: &ServiceRequest) -> bool {
+        let Some(db) = req.app_data::<web::Data<DBConnection>>() else {
+            return false;
+        };
+        let headers = req.headers();
+        if let Some(key) = ApiKey::from_headers(headers) {
+            let Ok(Some(claim)) = ApiKey::authenticate(db, key).await else {
+                return false;
+            };
+            req.extensions_mut().insert(claim);
             return true;
         }
-        let headers = req.headers();
-        if let Some(token) = headers.get("Authorization") {
-            let token = token.to_str().unwrap();
-            if let Ok(payload) = crate::encryption::Jwt::decode::<UserClaim>(token) {
-                req.extensions_mut().insert(payload);
-                return true;
-            }
+        let Some(token) = headers.get("Authorization") else {
+            return false;
+        };
+        let Ok(token) = token.to_str() else {
+            return false;
+        };
+        let Ok(payload) = crate::encryption::Jwt::decode::<UserClaim>(token) else {
+            return false;
+        };
+        if !Session::is_active(db, payload.sid).await.unwrap_or(false) {
+            return false;
+        }
+        req.extensions_mut().insert(payload);
+        true
+    }
+}
+
+/// Rejects requests without a valid token, see `Middleware::check_login`, except for the `PublicRoutes`.
+/// responses to impersonated requests get the admin id in the `X-Impersonated-By` header.
+pub struct Authentication {
+    public: Rc<PublicRoutes>,
+}
+
+impl Authentication {
+    pub fn new(public: PublicRoutes) -> Self {
+        Self {
+            public: Rc::new(public),
         }
-        false
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = AuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware {
+            service: Rc::new(service),
+            public: self.public.clone(),
+        }))
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: Rc<S>,
+    public: Rc<PublicRoutes>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let public = self.public.is_public(req.method(), req.path());
+        Box::pin(async move {
+            // public routes still get the claim of logged-in users, see `auth_user::OptionalAuthUser`
+            if !Middleware::check_login(&req).await && !public {
+                // a response instead of an error so the outer middlewares still see it, e.g. logging
+                let error = AppError::Response("session-expired".into(), StatusCode::UNAUTHORIZED);
+                return Ok(req.error_response(error).map_into_right_body());
+            }
+            let (impersonated_by, locale) = req
+                .extensions()
+                .get::<UserClaim>()
+                .map_or((None, None), |user| (user.impersonated_by, user.locale));
+            if let Some(locale) = locale {
+                locale.prefer();
+            }
+            let mut res = service.call(req).await?;
+            if let Some(admin_id) = impersonated_by {
+                res.headers_mut().insert(
+                    HeaderName::from_static(IMPERSONATED_BY_HEADER),
+                    HeaderValue::from(admin_id),
+                );
+            }
+            Ok(res.map_into_left_body())
+        })
     }
 }
@@ -0,0 +1,196 @@
+use std::env::var;
+use std::str::FromStr;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use bcrypt::HashParts;
+
+use crate::env_or;
+use crate::error::AppError;
+
+/// One password hashing algorithm, hashes are stored as PHC / modular crypt strings.
+pub trait Hasher: Send + Sync {
+    fn hash(&self, password: &str) -> Result<String, AppError>;
+
+    fn verify(&self, password: &str, hash: &str) -> bool;
+
+    /// true when the hash was made by this algorithm
+    fn handles(&self, hash: &str) -> bool;
+
+    /// true when the hash uses the configured parameters
+    fn is_current(&self, hash: &str) -> bool;
+}
+
+/// default, parameters from `PASSWORD_ARGON2_MEMORY_KIB`, `PASSWORD_ARGON2_ITERATIONS` and `PASSWORD_ARGON2_PARALLELISM`
+pub struct Argon2id {
+    params: Params,
+}
+
+impl Argon2id {
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Self, AppError> {
+        let params = Params::new(memory_kib, iterations, parallelism, None)
+            .map_err(|e| AppError::Message(format!("Invalid Argon2 parameters: {e}")))?;
+        Ok(Self { params })
+    }
+
+    pub fn from_env() -> Result<Self, AppError> {
+        Self::new(
+            env_or("PASSWORD_ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+            env_or("PASSWORD_ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+            env_or("PASSWORD_ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+        )
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl Hasher for Argon2id {
+    fn hash(&self, password: &str) -> Result<String, AppError> {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let hash = self
+            .argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| AppError::Message(e.to_string()))?;
+        Ok(hash.to_string())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return false;
+        };
+        // parameters are read from the hash, so older hashes still verify
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    }
+
+    fn handles(&self, hash: &str) -> bool {
+        hash.starts_with("$argon2id$")
+    }
+
+    fn is_current(&self, hash: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return false;
+        };
+        let Ok(params) = Params::try_from(&hash) else {
+            return false;
+        };
+        hash.version == Some(Version::V0x13.into())
+            && params.m_cost() == self.params.m_cost()
+            && params.t_cost() == self.params.t_cost()
+            && params.p_cost() == self.params.p_cost()
+    }
+}
+
+/// legacy hashes made before Argon2id, cost from `PASSWORD_BCRYPT_COST`
+pub struct Bcrypt {
+    cost: u32,
+}
+
+impl Bcrypt {
+    pub fn new(cost: u32) -> Self {
+        Self { cost }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env_or("PASSWORD_BCRYPT_COST", bcrypt::DEFAULT_COST))
+    }
+}
+
+impl Hasher for Bcrypt {
+    fn hash(&self, password: &str) -> Result<String, AppError> {
+        bcrypt::hash(password, self.cost).map_err(|e| AppError::Message(e.to_string()))
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+
+    fn handles(&self, hash: &str) -> bool {
+        ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+    }
+
+    fn is_current(&self, hash: &str) -> bool {
+        HashParts::from_str(hash).is_ok_and(|parts| parts.get_cost() == self.cost)
+    }
+}
+
+/// Hashes new passwords with the configured algorithm and still verifies the others,
+/// `needs_rehash` tells when a stored hash should be replaced after a successful login.
+pub struct PasswordHasher {
+    current: Box<dyn Hasher>,
+    legacy: Vec<Box<dyn Hasher>>,
+}
+
+impl PasswordHasher {
+    pub fn new(current: Box<dyn Hasher>, legacy: Vec<Box<dyn Hasher>>) -> Self {
+        Self { current, legacy }
+    }
+
+    /// `PASSWORD_HASHER` is `argon2id` (default) or `bcrypt`
+    pub fn from_env() -> Result<Self, AppError> {
+        let argon2id = Box::new(Argon2id::from_env()?);
+        let bcrypt = Box::new(Bcrypt::from_env());
+        let hasher = match var("PASSWORD_HASHER").as_deref() {
+            Ok("bcrypt") => Self::new(bcrypt, vec![argon2id]),
+            _ => Self::new(argon2id, vec![bcrypt]),
+        };
+        Ok(hasher)
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, AppError> {
+        self.current.hash(password)
+    }
+
+    pub fn verify(&self, password: &str, hash: &str) -> bool {
+        std::iter::once(&self.current)
+            .chain(&self.legacy)
+            .find(|hasher| hasher.handles(hash))
+            .is_some_and(|hasher| hasher.verify(password, hash))
+    }
+
+    /// true when the hash uses another algorithm or outdated parameters
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        !self.current.handles(hash) || !self.current.is_current(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Argon2id, Bcrypt, Hasher, PasswordHasher};
+
+    fn hasher() -> PasswordHasher {
+        PasswordHasher::new(
+            Box::new(Argon2id::new(1024, 1, 1).unwrap()),
+            vec![Box::new(Bcrypt::new(4))],
+        )
+    }
+
+    #[test]
+    fn should_hash_with_argon2id() {
+        let hash = hasher().hash("Password-123").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(hasher().verify("Password-123", &hash));
+        assert!(!hasher().verify("Password-124", &hash));
+        assert!(!hasher().needs_rehash(&hash));
+    }
+
+    #[test]
+    fn should_verify_and_rehash_legacy_hashes() {
+        let legacy = Bcrypt::new(4).hash("Password-123").unwrap();
+        assert!(hasher().verify("Password-123", &legacy));
+        assert!(hasher().needs_rehash(&legacy));
+
+        // same algorithm with weaker parameters
+        let weak = Argon2id::new(512, 1, 1)
+            .unwrap()
+            .hash("Password-123")
+            .unwrap();
+        assert!(hasher().verify("Password-123", &weak));
+        assert!(hasher().needs_rehash(&weak));
+    }
+}
@@ -0,0 +1,142 @@
+use actix_web::http::Method;
+
+/// How a public route is matched against the request path.
+#[derive(Clone, Debug)]
+pub enum PathMatch {
+    /// the whole path, e.g. `/`
+    Exact(String),
+    /// the path and everything under it, `/authorization` matches `/authorization/login` but not `/authorizations`
+    Prefix(String),
+    /// `*` matches within one segment and `**` any number of segments, e.g. `/ama/*/public`
+    Glob(String),
+}
+
+impl PathMatch {
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            PathMatch::Exact(exact) => path == exact,
+            PathMatch::Prefix(prefix) => {
+                let prefix = prefix.trim_end_matches('/');
+                path == prefix
+                    || path
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            }
+            PathMatch::Glob(pattern) => {
+                let pattern: Vec<&str> = pattern.split('/').collect();
+                let path: Vec<&str> = path.split('/').collect();
+                glob_segments(&pattern, &path)
+            }
+        }
+    }
+}
+
+fn glob_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            glob_segments(&pattern[1..], path)
+                || (!path.is_empty() && glob_segments(pattern, &path[1..]))
+        }
+        (Some(segment), Some(part)) => {
+            glob_segment(segment.as_bytes(), part.as_bytes())
+                && glob_segments(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+fn glob_segment(pattern: &[u8], part: &[u8]) -> bool {
+    match (pattern.first(), part.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_segment(&pattern[1..], part)
+                || (!part.is_empty() && glob_segment(pattern, &part[1..]))
+        }
+        (Some(c), Some(p)) => c == p && glob_segment(&pattern[1..], &part[1..]),
+        _ => false,
+    }
+}
+
+/// Methods and path of a route, shared with `rate_limit::RateLimits`.
+#[derive(Clone, Debug)]
+pub(crate) struct Route {
+    /// empty for every method
+    pub methods: Vec<Method>,
+    pub path: PathMatch,
+}
+
+impl Route {
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        (self.methods.is_empty() || self.methods.contains(method)) && self.path.matches(path)
+    }
+}
+
+/// Routes that don't require a login, every other route does.
+/// each module adds its own public routes in its `routes` function, e.g.
+/// `public.prefix("/authorization")`, and the table is given to `middleware::Authentication`.
+#[derive(Clone, Debug, Default)]
+pub struct PublicRoutes {
+    routes: Vec<Route>,
+}
+
+impl PublicRoutes {
+    /// public for the given methods only, all methods when `methods` is empty
+    pub fn add(&mut self, methods: &[Method], path: PathMatch) -> &mut Self {
+        self.routes.push(Route {
+            methods: methods.to_vec(),
+            path,
+        });
+        self
+    }
+
+    pub fn exact(&mut self, path: &str) -> &mut Self {
+        self.add(&[], PathMatch::Exact(path.into()))
+    }
+
+    pub fn prefix(&mut self, path: &str) -> &mut Self {
+        self.add(&[], PathMatch::Prefix(path.into()))
+    }
+
+    pub fn glob(&mut self, pattern: &str) -> &mut Self {
+        self.add(&[], PathMatch::Glob(pattern.into()))
+    }
+
+    pub fn is_public(&self, method: &Method, path: &str) -> bool {
+        self.routes.iter().any(|route| route.matches(method, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::Method;
+
+    use super::{PathMatch, PublicRoutes};
+
+    #[test]
+    fn should_match_exact_and_prefix_paths() {
+        let mut public = PublicRoutes::default();
+        public.exact("/").prefix("/authorization");
+        assert!(public.is_public(&Method::GET, "/"));
+        assert!(public.is_public(&Method::POST, "/authorization"));
+        assert!(public.is_public(&Method::POST, "/authorization/login"));
+        assert!(!public.is_public(&Method::GET, "/ama"));
+        assert!(!public.is_public(&Method::GET, "/authorizations"));
+        assert!(!public.is_public(&Method::GET, "/me"));
+    }
+
+    #[test]
+    fn should_match_globs_and_methods() {
+        let mut public = PublicRoutes::default();
+        public
+            .glob("/ama/*/public")
+            .glob("/docs/**")
+            .add(&[Method::GET], PathMatch::Exact("/status".into()));
+        assert!(public.is_public(&Method::GET, "/ama/12/public"));
+        assert!(!public.is_public(&Method::GET, "/ama/12/private"));
+        assert!(!public.is_public(&Method::GET, "/ama/12/3/public"));
+        assert!(public.is_public(&Method::GET, "/docs/api/v1.json"));
+        assert!(public.is_public(&Method::GET, "/status"));
+        assert!(!public.is_public(&Method::POST, "/status"));
+    }
+}
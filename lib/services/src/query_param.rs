@@ -11,8 +11,11 @@ use sqlx::{Arguments, Encode, Postgres, Type};
 use struct_iterable::Iterable;
 use validator::Validate;
 
+use crate::crud::policy::{Owned, Policy};
 use crate::error::AppError;
-use crate::Status;
+use crate::i18n::t_args;
+use crate::users::{State, UserStatus, UserType};
+use crate::{Country, Status};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Order {
@@ -109,6 +112,36 @@ pub struct Filter<T, Op = WhereOp> {
     pub op: Op,
 }
 
+impl<E: AsRef<str>> Filter<E, WhereOpEnum> {
+    /// where clause for enums stored as text, e.g. `UserStatus`
+    fn text_clause(
+        &self,
+        name: &str,
+        alias: &str,
+        args: &mut PgArguments,
+        bind_count: &mut Parameters,
+    ) -> Result<String, AppError> {
+        let values: Vec<String> = self.val.iter().map(|v| v.as_ref().to_string()).collect();
+        let clause = match (&self.op, values.first()) {
+            (WhereOpEnum::IN, Some(_)) => {
+                args.add(values);
+                format!("{alias}{name} = ANY({})", bind_count.next())
+            }
+            (op, Some(value)) => {
+                args.add(value.to_owned());
+                format!("{alias}{name} {op} {}", bind_count.next())
+            }
+            (_, None) => {
+                return Err(AppError::Response(
+                    t_args("invalid-parameter", &[("name", name.into())]),
+                    StatusCode::BAD_REQUEST,
+                ))
+            }
+        };
+        Ok(clause)
+    }
+}
+
 impl Type<Postgres> for Filter<NaiveDate> {
     fn type_info() -> <Postgres as sqlx::Database>::TypeInfo {
         <Postgres as sqlx::Database>::TypeInfo::with_name("date")
@@ -134,26 +167,46 @@ pub struct QueryParams<F: Iterable, O: Iterable, M = bool> {
     pub order: Option<O>,
 }
 
+/// Rows of a list query with the page they were taken from, see `response::Response::paginated`.
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u64,
+    pub limit: u64,
+}
+
 impl<F: Iterable, O: Iterable> QueryParams<F, O> {
     pub fn get_offset(&self, limit: u64) -> u64 {
         limit * (self.page.unwrap_or(1) - 1)
     }
 
+    /// limit of the rows, at most 100
+    pub fn get_limit(&self, default_limit: u64) -> u64 {
+        self.limit.unwrap_or(default_limit).min(100)
+    }
+
+    /// `items` fetched with the query of `build_query`, `limit` is the one it returned
+    pub fn page<T>(&self, items: Vec<T>, limit: u64) -> Page<T> {
+        Page {
+            items,
+            page: self.page.unwrap_or(1),
+            limit,
+        }
+    }
+
     /// Build scooby sql query based on query parameters `QueryParams`.
-    /// it returns reference to `scbooy` `Select` to add other queries, `PgArgument` to include more args, `Parameters` to increment binding in query.
+    /// it returns reference to `scbooy` `Select` to add other queries, `PgArgument` to include more args, `Parameters` to increment binding in query,
+    /// and the limit applied to the rows for `page`.
     /// pass `table_alias` when joins are required on the main table.
     pub fn build_query(
         &self,
         mut query: Select,
         alias: &str,
         default_limit: u64,
-    ) -> Result<(Select, PgArguments, Parameters), AppError> {
+    ) -> Result<(Select, PgArguments, Parameters, u64), AppError> {
         let mut args: PgArguments = PgArguments::default();
         let mut bind_count = Parameters::new();
-        let mut limit = self.limit.unwrap_or(default_limit);
-        if limit > 100 {
-            limit = 100;
-        }
+        let limit = self.get_limit(default_limit);
         query = query.limit(limit);
         let offset = self.get_offset(limit);
         if offset > 0 {
@@ -187,7 +240,7 @@ impl<F: Iterable, O: Iterable> QueryParams<F, O> {
                             ));
                         } else {
                             return Err(AppError::Response(
-                                format!("Invalid parameter for {name}. from and to must be set"),
+                                t_args("invalid-parameter-range", &[("name", name.into())]),
                                 StatusCode::BAD_REQUEST,
                             ));
                         }
@@ -200,7 +253,7 @@ impl<F: Iterable, O: Iterable> QueryParams<F, O> {
                         ));
                     } else {
                         return Err(AppError::Response(
-                            format!("Invalid parameter for {name}"),
+                            t_args("invalid-parameter", &[("name", name.into())]),
                             StatusCode::BAD_REQUEST,
                         ));
                     }
@@ -230,7 +283,7 @@ impl<F: Iterable, O: Iterable> QueryParams<F, O> {
                         ));
                     } else {
                         return Err(AppError::Response(
-                            format!("Invalid parameter for {name}.  pass from and to dates"),
+                            t_args("invalid-parameter-range", &[("name", name.into())]),
                             StatusCode::BAD_REQUEST,
                         ));
                     }
@@ -244,7 +297,7 @@ impl<F: Iterable, O: Iterable> QueryParams<F, O> {
                         args.add(filter.val[0]);
                     } else {
                         return Err(AppError::Response(
-                            format!("Invalid parameter for {name}. pass either true or false"),
+                            t_args("invalid-parameter-bool", &[("name", name.into())]),
                             StatusCode::BAD_REQUEST,
                         ));
                     }
@@ -258,14 +311,56 @@ impl<F: Iterable, O: Iterable> QueryParams<F, O> {
                         args.add(status);
                     } else {
                         return Err(AppError::Response(
-                            format!("Invalid parameter for {name}. Pass either Active or Inactive"),
+                            t_args("invalid-parameter-status", &[("name", name.into())]),
                             StatusCode::BAD_REQUEST,
                         ));
                     }
+                } else if let Some(Some(filter)) = value.downcast_ref::<EnumFilter<UserStatus>>() {
+                    query = query.where_(filter.text_clause(
+                        name,
+                        alias,
+                        &mut args,
+                        &mut bind_count,
+                    )?);
+                } else if let Some(Some(filter)) = value.downcast_ref::<EnumFilter<UserType>>() {
+                    query = query.where_(filter.text_clause(
+                        name,
+                        alias,
+                        &mut args,
+                        &mut bind_count,
+                    )?);
+                } else if let Some(Some(filter)) = value.downcast_ref::<EnumFilter<State>>() {
+                    query = query.where_(filter.text_clause(
+                        name,
+                        alias,
+                        &mut args,
+                        &mut bind_count,
+                    )?);
+                } else if let Some(Some(filter)) = value.downcast_ref::<EnumFilter<Country>>() {
+                    query = query.where_(filter.text_clause(
+                        name,
+                        alias,
+                        &mut args,
+                        &mut bind_count,
+                    )?);
                 }
             }
         }
-        Ok((query, args, bind_count))
+        Ok((query, args, bind_count, limit))
+    }
+
+    /// `build_query` limited to the rows `policy` gives access to, see `Policy::scope`.
+    pub fn build_scoped_query<T: Owned>(
+        &self,
+        query: Select,
+        alias: &str,
+        default_limit: u64,
+        policy: &Policy,
+    ) -> Result<(Select, PgArguments, Parameters, u64), AppError> {
+        let (query, mut args, mut bind_count, limit) =
+            self.build_query(query, alias, default_limit)?;
+        let query = policy.scope::<T>(query, &mut args, &mut bind_count, alias);
+        Ok((query, args, bind_count, limit))
     }
 }
 
@@ -314,11 +409,46 @@ mod tests {
     fn should_build_basic_query() {
         let params = example_params();
         let query = select("*").from("ama");
-        let (query, _, _) = params.build_query(query, "", 20).unwrap();
+        let (query, _, _, limit) = params.build_query(query, "", 20).unwrap();
+        assert_eq!(params.page(vec![1], limit).limit, 20);
         let sql = query.to_string();
         assert_eq!(
             sql, "SELECT * FROM ama WHERE content != $1 ORDER BY id DESC, content ASC LIMIT 20",
             "testing the basic query"
         );
     }
+
+    #[derive(Deserialize, Serialize, Debug, Iterable)]
+    pub struct UserFilterColumns {
+        pub status: EnumFilter<UserStatus>,
+        pub country: EnumFilter<Country>,
+    }
+
+    #[test]
+    fn should_filter_text_enums() {
+        let params: QueryParams<UserFilterColumns, OrderColumns> = QueryParams {
+            limit: None,
+            meta: None,
+            page: None,
+            filter_type: None,
+            filter: Some(UserFilterColumns {
+                status: Some(Filter {
+                    op: WhereOpEnum::IN,
+                    val: vec![UserStatus::Resigned, UserStatus::Terminated],
+                }),
+                country: Some(Filter {
+                    op: WhereOpEnum::EQ,
+                    val: vec![Country::CA],
+                }),
+            }),
+            order: None,
+        };
+        let (query, _, _, _) = params
+            .build_query(select("*").from("users"), "u.", 20)
+            .unwrap();
+        assert_eq!(
+            query.to_string(),
+            "SELECT * FROM users WHERE u.status = ANY($1) AND u.country = $2 LIMIT 20"
+        );
+    }
 }
@@ -1,14 +1,32 @@
 use serde::{Deserialize, Serialize};
 use strum_macros::Display;
 
+use crate::logging::RequestId;
+
 #[derive(Serialize, Deserialize, Display, Debug)]
 pub enum MessageType {
     Message,
     Email,
+    /// new user signed up, payload has `user_id` and optional `charge_id`
+    Registration,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Message {
     pub type_: MessageType,
     pub payload: String,
+    /// id of the api request that queued the message, to find its logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Message {
+    /// message with the id of the current request, see `logging::RequestId`
+    pub fn new(type_: MessageType, payload: String) -> Self {
+        Self {
+            type_,
+            payload,
+            request_id: RequestId::current().map(|id| id.0),
+        }
+    }
 }
@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::error::AppError;
+
+use super::{Decision, Limit, RateLimitStore, State};
+
+/// expired keys are dropped once the store holds this many
+const PRUNE_AT: usize = 10_000;
+
+/// Keeps the limits of this instance only, share it between the workers with an `Arc`.
+#[derive(Default)]
+pub struct MemoryStore {
+    states: Mutex<HashMap<String, (State, f64)>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn hit(&self, key: &str, limit: &Limit, now: f64) -> Result<Decision, AppError> {
+        let mut states = self
+            .states
+            .lock()
+            .map_err(|e| AppError::Message(e.to_string()))?;
+        if states.len() >= PRUNE_AT {
+            states.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+        let state = states
+            .get(key)
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(state, _)| *state);
+        let (state, decision) = limit.apply(state, now);
+        states.insert(key.into(), (state, limit.expires_at(&state)));
+        Ok(decision)
+    }
+}
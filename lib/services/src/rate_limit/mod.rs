@@ -0,0 +1,432 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::http::Method;
+use actix_web::HttpMessage;
+use async_trait::async_trait;
+use chrono::Utc;
+use http::StatusCode;
+
+use crate::client_ip::client_ip;
+use crate::error::AppError;
+use crate::middleware::UserClaim;
+use crate::public_routes::{PathMatch, Route};
+
+pub mod memory;
+pub mod postgres;
+
+const LIMIT_HEADER: &str = "ratelimit-limit";
+const REMAINING_HEADER: &str = "ratelimit-remaining";
+const RESET_HEADER: &str = "ratelimit-reset";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// bursts up to `requests`, refilled evenly over the window
+    TokenBucket,
+    /// at most `requests` in any window, weighted with the count of the previous window
+    SlidingWindow,
+}
+
+/// Who a limit applies to, requests without a user or API key fall back to the IP.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyBy {
+    Ip,
+    User,
+    ApiKey,
+}
+
+impl KeyBy {
+    fn key(&self, req: &ServiceRequest) -> String {
+        let claim = req.extensions().get::<UserClaim>().cloned();
+        match (self, claim) {
+            (KeyBy::User, Some(user)) => format!("user:{}", user.id),
+            (
+                KeyBy::ApiKey,
+                Some(UserClaim {
+                    api_key_id: Some(id),
+                    ..
+                }),
+            ) => format!("key:{id}"),
+            _ => {
+                let ip = client_ip(req.request());
+                format!("ip:{}", ip.as_deref().unwrap_or("unknown"))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Limit {
+    pub algorithm: Algorithm,
+    pub key: KeyBy,
+    pub requests: u32,
+    pub window_seconds: u64,
+}
+
+impl Limit {
+    pub fn token_bucket(requests: u32, window_seconds: u64) -> Self {
+        Self {
+            algorithm: Algorithm::TokenBucket,
+            key: KeyBy::Ip,
+            requests,
+            window_seconds,
+        }
+    }
+
+    pub fn sliding_window(requests: u32, window_seconds: u64) -> Self {
+        Self {
+            algorithm: Algorithm::SlidingWindow,
+            key: KeyBy::Ip,
+            requests,
+            window_seconds,
+        }
+    }
+
+    pub fn key_by(mut self, key: KeyBy) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// the state is no longer needed after this time, the client is back to a full limit
+    pub fn expires_at(&self, state: &State) -> f64 {
+        state.updated_at + 2.0 * self.window_seconds as f64
+    }
+
+    /// count a request at `now`, in unix seconds, against the stored state of its key
+    pub fn apply(&self, state: Option<State>, now: f64) -> (State, Decision) {
+        let requests = self.requests as f64;
+        let window = self.window_seconds.max(1) as f64;
+        match self.algorithm {
+            Algorithm::TokenBucket => {
+                let rate = requests / window;
+                let mut tokens = match state {
+                    Some(state) => {
+                        (state.value + (now - state.updated_at).max(0.0) * rate).min(requests)
+                    }
+                    None => requests,
+                };
+                let allowed = tokens >= 1.0;
+                if allowed {
+                    tokens -= 1.0;
+                }
+                let decision = Decision {
+                    allowed,
+                    limit: self.requests,
+                    remaining: tokens.floor() as u32,
+                    reset: ((requests - tokens) / rate).ceil() as u64,
+                    retry_after: (!allowed).then(|| ((1.0 - tokens) / rate).ceil().max(1.0) as u64),
+                };
+                let state = State {
+                    value: tokens,
+                    previous: 0.0,
+                    updated_at: now,
+                };
+                (state, decision)
+            }
+            Algorithm::SlidingWindow => {
+                let start = (now / window).floor() * window;
+                let (mut current, previous) = match state {
+                    Some(state) if state.updated_at == start => (state.value, state.previous),
+                    Some(state) if state.updated_at == start - window => (0.0, state.value),
+                    _ => (0.0, 0.0),
+                };
+                let elapsed = now - start;
+                let weight = 1.0 - elapsed / window;
+                let allowed = previous * weight + current + 1.0 <= requests;
+                if allowed {
+                    current += 1.0;
+                }
+                let retry_after = (!allowed).then(|| {
+                    let wait = if current + 1.0 <= requests {
+                        // until enough of the previous window slides out
+                        window * (1.0 - (requests - 1.0 - current) / previous) - elapsed
+                    } else {
+                        (window - elapsed) + window * (1.0 - (requests - 1.0) / current).max(0.0)
+                    };
+                    wait.ceil().max(1.0) as u64
+                });
+                let decision = Decision {
+                    allowed,
+                    limit: self.requests,
+                    remaining: (requests - previous * weight - current).floor().max(0.0) as u32,
+                    reset: (window - elapsed).ceil() as u64,
+                    retry_after,
+                };
+                let state = State {
+                    value: current,
+                    previous,
+                    updated_at: start,
+                };
+                (state, decision)
+            }
+        }
+    }
+}
+
+/// Stored between requests, `value` is the tokens left or the count of the current window,
+/// `previous` the count of the previous window and `updated_at` the last refill or the window start.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct State {
+    pub value: f64,
+    pub previous: f64,
+    pub updated_at: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// seconds until the limit is fully available again
+    pub reset: u64,
+    /// seconds to wait before retrying a rejected request
+    pub retry_after: Option<u64>,
+}
+
+impl Decision {
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            (LIMIT_HEADER, self.limit as u64),
+            (REMAINING_HEADER, self.remaining as u64),
+            (RESET_HEADER, self.reset),
+        ];
+        for (name, value) in values {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+        }
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+    }
+}
+
+/// Where the state of every key is kept, `memory::MemoryStore` for a single instance
+/// and `postgres::PostgresStore` when the instances have to share the limits, e.g. on Lambda.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// count a request of `key` and return if it's allowed
+    async fn hit(&self, key: &str, limit: &Limit, now: f64) -> Result<Decision, AppError>;
+}
+
+#[derive(Clone, Debug)]
+struct LimitedRoute {
+    name: String,
+    route: Route,
+    limit: Limit,
+}
+
+/// Limits of the routes, each module adds its own in its `routes` function,
+/// the first matching route is used and the others aren't limited.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    routes: Vec<LimitedRoute>,
+}
+
+impl RateLimits {
+    /// `name` groups the requests of the route in the store, all methods when `methods` is empty
+    pub fn add(
+        &mut self,
+        name: &str,
+        methods: &[Method],
+        path: PathMatch,
+        limit: Limit,
+    ) -> &mut Self {
+        self.routes.push(LimitedRoute {
+            name: name.into(),
+            route: Route {
+                methods: methods.to_vec(),
+                path,
+            },
+            limit,
+        });
+        self
+    }
+
+    fn find(&self, method: &Method, path: &str) -> Option<&LimitedRoute> {
+        self.routes
+            .iter()
+            .find(|limited| limited.route.matches(method, path))
+    }
+}
+
+/// Responds with 429 when a client goes over the limit of the route, see `RateLimits`.
+/// every limited response gets the `RateLimit-*` headers, and `Retry-After` when rejected.
+/// wrap it before `middleware::Authentication` so limits can be keyed by user.
+pub struct RateLimiter {
+    limits: Rc<RateLimits>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, store: Arc<dyn RateLimitStore>) -> Self {
+        Self {
+            limits: Rc::new(limits),
+            store,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            limits: self.limits.clone(),
+            store: self.store.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    limits: Rc<RateLimits>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+        let limited = self.limits.find(req.method(), req.path()).cloned();
+        Box::pin(async move {
+            let Some(limited) = limited else {
+                return Ok(service.call(req).await?.map_into_left_body());
+            };
+            let key = format!("{}:{}", limited.name, limited.limit.key.key(&req));
+            let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+            let decision = match store.hit(&key, &limited.limit, now).await {
+                Ok(decision) => decision,
+                Err(e) => {
+                    // the api stays available when the store is down
+                    tracing::error!("{e} - rate limit {key}");
+                    return Ok(service.call(req).await?.map_into_left_body());
+                }
+            };
+            if !decision.allowed {
+                let error =
+                    AppError::Response("too-many-requests".into(), StatusCode::TOO_MANY_REQUESTS);
+                let mut res = req.error_response(error);
+                decision.add_headers(res.headers_mut());
+                return Ok(res.map_into_right_body());
+            }
+            let mut res = service.call(req).await?;
+            decision.add_headers(res.headers_mut());
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::Method;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use http::StatusCode;
+
+    use crate::public_routes::PathMatch;
+
+    use super::memory::MemoryStore;
+    use super::{Limit, RateLimiter, RateLimits};
+
+    #[test]
+    fn should_refill_token_bucket() {
+        // 2 requests per 8 seconds
+        let limit = Limit::token_bucket(2, 8);
+        let (state, first) = limit.apply(None, 100.0);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        let (state, second) = limit.apply(Some(state), 100.0);
+        assert!(second.allowed);
+        let (state, third) = limit.apply(Some(state), 101.0);
+        assert!(!third.allowed);
+        assert_eq!(third.retry_after, Some(3));
+        // a token every 4 seconds
+        let (_, fourth) = limit.apply(Some(state), 105.0);
+        assert!(fourth.allowed);
+        assert_eq!(fourth.remaining, 0);
+    }
+
+    #[test]
+    fn should_weight_previous_sliding_window() {
+        let limit = Limit::sliding_window(4, 10);
+        let mut state = None;
+        for _ in 0..4 {
+            let (next, decision) = limit.apply(state, 105.0);
+            assert!(decision.allowed);
+            state = Some(next);
+        }
+        let (next, decision) = limit.apply(state, 109.0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.reset, 1);
+        // half of the previous window still counts, 2 of 4
+        let (next, decision) = limit.apply(Some(next), 115.0);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+        let (next, decision) = limit.apply(Some(next), 115.0);
+        assert!(decision.allowed);
+        let (_, decision) = limit.apply(Some(next), 115.0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, Some(3));
+    }
+
+    #[actix_web::test]
+    async fn should_reject_with_headers() {
+        let mut limits = RateLimits::default();
+        limits.add(
+            "login",
+            &[Method::POST],
+            PathMatch::Exact("/login".into()),
+            Limit::sliding_window(1, 60),
+        );
+        let app = init_service(
+            App::new()
+                .wrap(RateLimiter::new(limits, Arc::new(MemoryStore::default())))
+                .route("/login", web::post().to(HttpResponse::Ok))
+                .route("/other", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        // a forged X-Forwarded-For doesn't change the client
+        let login = |forwarded: &str| {
+            TestRequest::post()
+                .uri("/login")
+                .peer_addr("203.0.113.7:4000".parse().unwrap())
+                .insert_header(("X-Forwarded-For", forwarded))
+                .to_request()
+        };
+        let res = call_service(&app, login("198.51.100.1")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("ratelimit-remaining").unwrap(), "0");
+        let res = call_service(&app, login("198.51.100.2")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key("retry-after"));
+        let other = TestRequest::post().uri("/other").to_request();
+        let res = call_service(&app, other).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key("ratelimit-limit"));
+    }
+}
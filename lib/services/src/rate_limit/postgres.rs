@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+
+use crate::db::DBConnection;
+use crate::error::AppError;
+
+use super::{Decision, Limit, RateLimitStore, State};
+
+/// Shares the limits between instances in the `rate_limits` table, each key is locked while it's updated.
+pub struct PostgresStore {
+    db: DBConnection,
+}
+
+impl PostgresStore {
+    pub fn new(db: DBConnection) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn hit(&self, key: &str, limit: &Limit, now: f64) -> Result<Decision, AppError> {
+        let mut tx = self.db.begin().await?;
+        // the row has to exist to be locked, an expired row is the same as no row
+        sqlx::query!(
+            r#"
+                INSERT INTO rate_limits (key, value, previous, updated_at, expires_at)
+                VALUES ($1, 0, 0, 0, 0)
+                ON CONFLICT (key) DO NOTHING
+            "#,
+            key
+        )
+        .execute(&mut *tx)
+        .await?;
+        let row = sqlx::query!(
+            "SELECT value, previous, updated_at, expires_at FROM rate_limits WHERE key = $1 FOR UPDATE",
+            key
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let state = (row.expires_at > now).then_some(State {
+            value: row.value,
+            previous: row.previous,
+            updated_at: row.updated_at,
+        });
+        let (state, decision) = limit.apply(state, now);
+        sqlx::query!(
+            r#"
+                UPDATE rate_limits SET value = $2, previous = $3, updated_at = $4, expires_at = $5
+                WHERE key = $1
+            "#,
+            key,
+            state.value,
+            state.previous,
+            state.updated_at,
+            limit.expires_at(&state)
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        if rand::random::<u8>() == 0 {
+            sqlx::query!("DELETE FROM rate_limits WHERE expires_at < $1", now)
+                .execute(&self.db)
+                .await?;
+        }
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::DBConnection;
+    use crate::rate_limit::{Limit, RateLimitStore};
+
+    use super::PostgresStore;
+
+    #[sqlx::test(migrations = "../../migrations")]
+    async fn should_share_limits_between_instances(pool: DBConnection) {
+        let limit = Limit::sliding_window(2, 60);
+        let first = PostgresStore::new(pool.clone());
+        let second = PostgresStore::new(pool);
+        assert!(
+            first
+                .hit("login:ip:1", &limit, 100.0)
+                .await
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            second
+                .hit("login:ip:1", &limit, 101.0)
+                .await
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !first
+                .hit("login:ip:1", &limit, 102.0)
+                .await
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            first
+                .hit("login:ip:2", &limit, 102.0)
+                .await
+                .unwrap()
+                .allowed
+        );
+        // the window is over and the state expired
+        assert!(
+            second
+                .hit("login:ip:1", &limit, 300.0)
+                .await
+                .unwrap()
+                .allowed
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use actix_web::rt::task::spawn_blocking;
+use async_trait::async_trait;
+
+use crate::error::AppError;
+
+use super::{ErrorReport, ErrorSink};
+
+/// Appends every report as a JSON line to `path`, or logs it when there is no file.
+pub struct FileSink {
+    path: Option<PathBuf>,
+}
+
+impl FileSink {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl ErrorSink for FileSink {
+    async fn report(&self, report: &ErrorReport) -> Result<(), AppError> {
+        let line = serde_json::to_string(report)?;
+        let Some(path) = &self.path else {
+            tracing::error!(report = %line, "{}", report.message);
+            return Ok(());
+        };
+        let path = path.clone();
+        // the file is written on the blocking pool, not on the worker handling requests
+        spawn_blocking(move || {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| AppError::Message(format!("{e} - {}", path.display())))?;
+            writeln!(file, "{line}").map_err(|e| AppError::Message(e.to_string()))
+        })
+        .await
+        .map_err(|e| AppError::Message(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reporting::{ErrorReport, ErrorSink};
+
+    use super::FileSink;
+
+    #[actix_web::test]
+    async fn should_append_json_lines() {
+        let path = std::env::temp_dir().join(format!("errors-{}.log", std::process::id()));
+        let sink = FileSink::new(Some(path.clone()));
+        sink.report(&ErrorReport::new("first")).await.unwrap();
+        sink.report(&ErrorReport::new("second").extra("user_id", 1))
+            .await
+            .unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["message"], "second");
+        assert_eq!(lines[1]["extra"]["user_id"], 1);
+    }
+}
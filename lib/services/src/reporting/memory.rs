@@ -0,0 +1,33 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::error::AppError;
+
+use super::{ErrorReport, ErrorSink};
+
+/// Keeps the reports in memory, for tests.
+#[derive(Default)]
+pub struct MemorySink {
+    reports: Mutex<Vec<ErrorReport>>,
+}
+
+impl MemorySink {
+    pub fn reports(&self) -> Vec<ErrorReport> {
+        self.reports
+            .lock()
+            .map(|reports| reports.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl ErrorSink for MemorySink {
+    async fn report(&self, report: &ErrorReport) -> Result<(), AppError> {
+        self.reports
+            .lock()
+            .map_err(|e| AppError::Message(e.to_string()))?
+            .push(report.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::cell::RefCell;
+use std::env::var;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::HttpMessage;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::error::AppError;
+use crate::logging::RequestId;
+use crate::middleware::UserClaim;
+
+pub mod file;
+pub mod memory;
+pub mod sentry;
+
+/// most recent breadcrumbs kept for a report
+const MAX_BREADCRUMBS: usize = 50;
+
+tokio::task_local! {
+    static BREADCRUMBS: RefCell<Vec<Breadcrumb>>;
+}
+
+/// Step taken before an error, e.g. `breadcrumb("payment", "card charged")`, sent with the report.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Breadcrumb {
+    pub timestamp: DateTime<Utc>,
+    pub category: String,
+    pub message: String,
+}
+
+/// record a step of the current request or queue message, ignored outside of them
+pub fn breadcrumb(category: &str, message: impl Into<String>) {
+    let crumb = Breadcrumb {
+        timestamp: Utc::now(),
+        category: category.into(),
+        message: message.into(),
+    };
+    let _ = BREADCRUMBS.try_with(|crumbs| {
+        let mut crumbs = crumbs.borrow_mut();
+        if crumbs.len() == MAX_BREADCRUMBS {
+            crumbs.remove(0);
+        }
+        crumbs.push(crumb);
+    });
+}
+
+/// run `f` with its own breadcrumbs, see `breadcrumb`
+pub async fn with_breadcrumbs<F: Future>(f: F) -> F::Output {
+    BREADCRUMBS.scope(RefCell::new(vec![]), f).await
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ErrorReport {
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+    /// response status of a failed request
+    pub status: Option<u16>,
+    pub request_id: Option<String>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub user_id: Option<i32>,
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// anything else that helps to reproduce the error, e.g. the queue message
+    pub extra: Map<String, Value>,
+}
+
+impl ErrorReport {
+    /// report with the breadcrumbs recorded so far
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            timestamp: Utc::now(),
+            status: None,
+            request_id: RequestId::current().map(|id| id.0),
+            method: None,
+            path: None,
+            user_id: None,
+            breadcrumbs: BREADCRUMBS
+                .try_with(|crumbs| crumbs.borrow().clone())
+                .unwrap_or_default(),
+            extra: Map::new(),
+        }
+    }
+
+    pub fn extra(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.extra.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Where errors are sent, `sentry::SentrySink` when `ERROR_REPORTING_DSN` is set,
+/// `file::FileSink` for `ERROR_REPORTING_FILE` and the logs otherwise.
+#[async_trait]
+pub trait ErrorSink: Send + Sync {
+    async fn report(&self, report: &ErrorReport) -> Result<(), AppError>;
+}
+
+pub fn sink_from_env() -> Result<Arc<dyn ErrorSink>, AppError> {
+    let set = |name| var(name).ok().filter(|value| !value.is_empty());
+    if let Some(dsn) = set("ERROR_REPORTING_DSN") {
+        return Ok(Arc::new(sentry::SentrySink::new(&dsn)?));
+    }
+    Ok(Arc::new(file::FileSink::new(
+        set("ERROR_REPORTING_FILE").map(Into::into),
+    )))
+}
+
+/// send the report, a sink that fails is only logged
+pub async fn report(sink: &dyn ErrorSink, report: ErrorReport) {
+    if let Err(e) = sink.report(&report).await {
+        tracing::error!("{e} - error report {}", report.message);
+    }
+}
+
+/// Reports the 5xx responses with the request, user and breadcrumbs.
+/// reports are sent before the response, Lambda freezes the runtime once it's returned,
+/// so anything left in the background is lost. the Sentry sink times out, see `sentry::TIMEOUT_SECONDS`.
+/// wrap it right before `logging::RequestLogger` so the request id is set.
+pub struct ErrorReporting {
+    sink: Arc<dyn ErrorSink>,
+}
+
+impl ErrorReporting {
+    pub fn new(sink: Arc<dyn ErrorSink>) -> Self {
+        Self { sink }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ErrorReporting
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = ErrorReportingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorReportingMiddleware {
+            service: Rc::new(service),
+            sink: self.sink.clone(),
+        }))
+    }
+}
+
+pub struct ErrorReportingMiddleware<S> {
+    service: Rc<S>,
+    sink: Arc<dyn ErrorSink>,
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorReportingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let sink = self.sink.clone();
+        Box::pin(with_breadcrumbs(async move {
+            let res = service.call(req).await?;
+            if !res.status().is_server_error() {
+                return Ok(res);
+            }
+            let message = match res.response().error() {
+                Some(e) => e.to_string(),
+                None => res.status().to_string(),
+            };
+            let request = res.request();
+            let mut error = ErrorReport::new(message);
+            error.status = Some(res.status().as_u16());
+            error.method = Some(request.method().to_string());
+            error.path = Some(request.path().into());
+            error.request_id = request
+                .extensions()
+                .get::<RequestId>()
+                .map(|id| id.0.clone());
+            error.user_id = request.extensions().get::<UserClaim>().map(|user| user.id);
+            report(sink.as_ref(), error).await;
+            Ok(res)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use http::StatusCode;
+
+    use crate::error::AppError;
+    use crate::logging::{RequestLogger, REQUEST_ID_HEADER};
+
+    use super::memory::MemorySink;
+    use super::{breadcrumb, ErrorReporting};
+
+    #[actix_web::test]
+    async fn should_report_server_errors_with_context() {
+        let sink = Arc::new(MemorySink::default());
+        let app = init_service(
+            App::new()
+                .wrap(ErrorReporting::new(sink.clone()))
+                .wrap(RequestLogger)
+                .route(
+                    "/fail",
+                    web::get().to(|| async {
+                        breadcrumb("db", "loading user");
+                        Err::<HttpResponse, _>(AppError::Message("connection refused".into()))
+                    }),
+                )
+                .route(
+                    "/missing",
+                    web::get()
+                        .to(|| async { Err::<HttpResponse, _>(AppError::NotFound("User".into())) }),
+                ),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/fail")
+            .insert_header((REQUEST_ID_HEADER, "abc-123"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let res = call_service(&app, TestRequest::get().uri("/missing").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let reports = sink.reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].message, "connection refused");
+        assert_eq!(reports[0].status, Some(500));
+        assert_eq!(reports[0].path.as_deref(), Some("/fail"));
+        assert_eq!(reports[0].request_id.as_deref(), Some("abc-123"));
+        assert_eq!(reports[0].breadcrumbs[0].message, "loading user");
+    }
+}
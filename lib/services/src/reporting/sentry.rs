@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand::RngCore;
+use reqwest::Url;
+use serde_json::{json, Value};
+
+use crate::error::AppError;
+
+use super::{ErrorReport, ErrorSink};
+
+/// a report is dropped when the server doesn't answer in time
+const TIMEOUT_SECONDS: u64 = 5;
+
+/// Sends reports to Sentry, or any server speaking its store protocol, e.g. GlitchTip.
+/// the DSN looks like `https://<public key>@<host>/<project id>`.
+pub struct SentrySink {
+    store_url: String,
+    public_key: String,
+    client: reqwest::Client,
+}
+
+impl SentrySink {
+    pub fn new(dsn: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::Message("Invalid ERROR_REPORTING_DSN".into());
+        let url = Url::parse(dsn).map_err(|_| invalid())?;
+        let public_key = url.username();
+        let project_id = url.path().trim_matches('/');
+        if public_key.is_empty() || project_id.is_empty() {
+            return Err(invalid());
+        }
+        let host = url.host_str().ok_or_else(invalid)?;
+        let port = url
+            .port()
+            .map(|port| format!(":{port}"))
+            .unwrap_or_default();
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+            .build()
+            .map_err(|e| AppError::Message(e.to_string()))?;
+        Ok(Self {
+            store_url: format!("{}://{host}{port}/api/{project_id}/store/", url.scheme()),
+            public_key: public_key.into(),
+            client,
+        })
+    }
+
+    fn event(report: &ErrorReport) -> Value {
+        let mut event_id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut event_id);
+        let breadcrumbs: Vec<Value> = report
+            .breadcrumbs
+            .iter()
+            .map(|crumb| {
+                json!({
+                    "timestamp": crumb.timestamp.timestamp(),
+                    "category": crumb.category,
+                    "message": crumb.message,
+                })
+            })
+            .collect();
+        json!({
+            "event_id": hex::encode(event_id),
+            "timestamp": report.timestamp.to_rfc3339(),
+            "level": "error",
+            "platform": "other",
+            "message": { "formatted": report.message },
+            "tags": {
+                "request_id": report.request_id,
+                "status": report.status,
+            },
+            "user": report.user_id.map(|id| json!({ "id": id.to_string() })),
+            "request": {
+                "method": report.method,
+                "url": report.path,
+            },
+            "breadcrumbs": { "values": breadcrumbs },
+            "extra": report.extra,
+        })
+    }
+}
+
+#[async_trait]
+impl ErrorSink for SentrySink {
+    async fn report(&self, report: &ErrorReport) -> Result<(), AppError> {
+        let auth = format!(
+            "Sentry sentry_version=7, sentry_client=services/0.1, sentry_key={}",
+            self.public_key
+        );
+        let response = self
+            .client
+            .post(&self.store_url)
+            .header("X-Sentry-Auth", auth)
+            .json(&Self::event(report))
+            .send()
+            .await
+            .map_err(|e| AppError::Message(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(AppError::Message(format!(
+                "Sentry responded with {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{body_partial_json, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::reporting::{ErrorReport, ErrorSink};
+
+    use super::SentrySink;
+
+    #[test]
+    fn should_reject_invalid_dsn() {
+        assert!(SentrySink::new("not a dsn").is_err());
+        assert!(SentrySink::new("https://sentry.io/42").is_err());
+    }
+
+    #[actix_web::test]
+    async fn should_send_event_to_store() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/42/store/"))
+            .and(header_exists("X-Sentry-Auth"))
+            .and(body_partial_json(serde_json::json!({
+                "message": { "formatted": "connection refused" },
+                "user": { "id": "7" },
+                "request": { "method": "GET", "url": "/me" },
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let dsn = server.uri().replace("http://", "http://public@") + "/42";
+        let mut report = ErrorReport::new("connection refused");
+        report.user_id = Some(7);
+        report.method = Some("GET".into());
+        report.path = Some("/me".into());
+        SentrySink::new(&dsn)
+            .unwrap()
+            .report(&report)
+            .await
+            .unwrap();
+    }
+}
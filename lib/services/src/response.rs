@@ -1,28 +1,181 @@
-use actix_web::HttpResponse;
-use http::StatusCode;
+use actix_web::http::header::LOCATION;
+use actix_web::{HttpRequest, HttpResponse};
 use serde::Serialize;
-use serde_json::json;
 
 use crate::error::AppError;
+use crate::query_param::Page;
+
+/// Body of the successful responses, `{"data": ..., "meta": ..., "links": ...}`.
+/// failed requests get an `error::Problem` instead.
+#[derive(Serialize, Debug)]
+pub struct Envelope<T: Serialize> {
+    pub data: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub links: Option<Links>,
+}
+
+impl<T: Serialize> Envelope<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data,
+            meta: None,
+            links: None,
+        }
+    }
+}
+
+/// pagination of list responses, `count` is the number of items in this page
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Meta {
+    pub page: u64,
+    pub limit: u64,
+    pub count: usize,
+}
+
+/// urls of the current, next and previous pages, with the same filters and order
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Links {
+    #[serde(rename = "self")]
+    pub self_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+}
+
+impl Links {
+    fn for_page<T>(req: &HttpRequest, page: &Page<T>) -> Self {
+        let url = |number: u64| {
+            let mut query: Vec<&str> = req
+                .query_string()
+                .split('&')
+                .filter(|param| !param.is_empty() && !param.starts_with("page="))
+                .collect();
+            let page = format!("page={number}");
+            query.push(&page);
+            format!("{}?{}", req.path(), query.join("&"))
+        };
+        // a full page may be followed by another one, the total isn't counted
+        let full = page.items.len() as u64 >= page.limit;
+        Self {
+            self_: url(page.page),
+            next: full.then(|| url(page.page + 1)),
+            prev: (page.page > 1).then(|| url(page.page - 1)),
+        }
+    }
+}
 
 pub struct Response;
 
 impl Response {
-    pub fn inserted_id(id: i32) -> Result<(String, StatusCode), AppError> {
-        let json = json!({
-            "id": id
-        });
-        Ok((json.to_string(), StatusCode::CREATED))
+    pub fn ok<T: Serialize>(data: T) -> Result<HttpResponse, AppError> {
+        Ok(HttpResponse::Ok().json(Envelope::new(data)))
     }
 
-    pub fn result<T: Serialize>(result: T) -> Result<HttpResponse, AppError> {
-        let json = json!({
-            "result": result
-        });
-        Ok(HttpResponse::Ok().json(json))
+    /// 201 with the url of the new resource in the `Location` header, e.g. `/ama/1`,
+    /// `None` when the caller can't read it there
+    pub fn created<T: Serialize>(
+        data: T,
+        location: Option<&str>,
+    ) -> Result<HttpResponse, AppError> {
+        let mut res = HttpResponse::Created();
+        if let Some(location) = location {
+            res.insert_header((LOCATION, location));
+        }
+        Ok(res.json(Envelope::new(data)))
     }
 
-    pub fn ok<'a>() -> Result<&'a str, AppError> {
-        Ok("Ok")
+    /// 202 for work that finishes after the response, e.g. sending an email
+    pub fn accepted() -> Result<HttpResponse, AppError> {
+        Ok(HttpResponse::Accepted().finish())
+    }
+
+    pub fn no_content() -> Result<HttpResponse, AppError> {
+        Ok(HttpResponse::NoContent().finish())
+    }
+
+    /// items of the page with its `meta` and `links`
+    pub fn paginated<T: Serialize>(
+        req: &HttpRequest,
+        page: Page<T>,
+    ) -> Result<HttpResponse, AppError> {
+        let links = Links::for_page(req, &page);
+        let envelope = Envelope {
+            meta: Some(Meta {
+                page: page.page,
+                limit: page.limit,
+                count: page.items.len(),
+            }),
+            links: Some(links),
+            data: page.items,
+        };
+        Ok(HttpResponse::Ok().json(envelope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+    use http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::query_param::Page;
+
+    use super::Response;
+
+    async fn body(res: actix_web::HttpResponse) -> Value {
+        serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn should_wrap_data_in_envelope() {
+        let res = Response::ok(json!({ "token": "abc" })).unwrap();
+        assert_eq!(body(res).await, json!({ "data": { "token": "abc" } }));
+
+        let res = Response::created(json!({ "id": 7 }), Some("/ama/7")).unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get("location").unwrap(), "/ama/7");
+        let res = Response::created(json!({ "id": 7 }), None).unwrap();
+        assert!(res.headers().get("location").is_none());
+
+        assert_eq!(Response::accepted().unwrap().status(), StatusCode::ACCEPTED);
+        assert_eq!(
+            Response::no_content().unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+    }
+
+    #[actix_web::test]
+    async fn should_link_pages() {
+        let req = TestRequest::get()
+            .uri("/ama?page=2&limit=2&order%5Bname%5D=ASC")
+            .to_http_request();
+        let page = Page {
+            items: vec![1, 2],
+            page: 2,
+            limit: 2,
+        };
+        let first = body(Response::paginated(&req, page).unwrap()).await;
+        assert_eq!(first["data"], json!([1, 2]));
+        assert_eq!(first["meta"], json!({ "page": 2, "limit": 2, "count": 2 }));
+        assert_eq!(
+            first["links"]["next"],
+            "/ama?limit=2&order%5Bname%5D=ASC&page=3"
+        );
+        assert_eq!(
+            first["links"]["prev"],
+            "/ama?limit=2&order%5Bname%5D=ASC&page=1"
+        );
+
+        let last = Page {
+            items: vec![3],
+            page: 3,
+            limit: 2,
+        };
+        let last = body(Response::paginated(&req, last).unwrap()).await;
+        assert!(last["links"].get("next").is_none());
     }
 }
@@ -0,0 +1,67 @@
+use chrono::NaiveDateTime;
+
+use crate::db::DBConnection;
+use crate::error::AppError;
+
+/// Server side record of an issued token, `UserClaim.sid` points to it so tokens can be revoked.
+pub struct Session;
+
+impl Session {
+    pub async fn create(
+        db: &DBConnection,
+        user_id: i32,
+        expires_at: NaiveDateTime,
+    ) -> Result<i32, AppError> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO sessions (user_id, expires_at) VALUES ($1, $2) RETURNING id",
+            user_id,
+            expires_at
+        )
+        .fetch_one(db)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn is_active(db: &DBConnection, id: i32) -> Result<bool, AppError> {
+        let active = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM sessions WHERE id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+                ) AS "active!"
+            "#,
+            id
+        )
+        .fetch_one(db)
+        .await?;
+        Ok(active)
+    }
+
+    pub async fn revoke(db: &DBConnection, id: i32) -> Result<(), AppError> {
+        sqlx::query!(
+            "UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL",
+            id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// revoke every session of the user, except `keep` when the current session should stay logged in
+    pub async fn revoke_all(
+        db: &DBConnection,
+        user_id: i32,
+        keep: Option<i32>,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+                UPDATE sessions SET revoked_at = NOW()
+                WHERE user_id = $1 AND revoked_at IS NULL AND id IS DISTINCT FROM $2
+            "#,
+            user_id,
+            keep
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
@@ -1,6 +1,54 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use strum_macros::AsRefStr;
 
-#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, Eq, PartialEq, Hash)]
+use crate::db::DBConnection;
+use crate::error::AppError;
+use crate::i18n::Locale;
+use crate::Country;
+
+/// Row of the `users` table without the password and login counters.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub user_name: String,
+    pub email: String,
+    pub phone: String,
+    pub user_type: UserType,
+    pub status: UserStatus,
+    pub state: State,
+    pub country: Country,
+    pub photo: Option<String>,
+    pub locale: Option<Locale>,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub phone_verified_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl User {
+    pub async fn find(db: &DBConnection, id: i32) -> Result<Self, AppError> {
+        let user = sqlx::query_as!(
+            Self,
+            r#"
+                SELECT
+                    id, first_name, last_name, user_name, email, phone,
+                    type AS "user_type: UserType", status AS "status: UserStatus",
+                    state AS "state: State", country AS "country: Country",
+                    photo, locale AS "locale: Locale", email_verified_at, phone_verified_at, created_at
+                FROM users
+                WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(db)
+        .await?;
+        user.ok_or_else(|| AppError::NotFound("User".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::Type, Clone, Copy, Eq, PartialEq, Hash, AsRefStr)]
 #[sqlx(type_name = "user_type")]
 pub enum UserType {
     Admin,
@@ -23,7 +71,7 @@ pub enum Gender {
     Unknown,
 }
 
-#[derive(Deserialize, Serialize, Clone, sqlx::Type, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, sqlx::Type, Debug, PartialEq, AsRefStr)]
 pub enum UserStatus {
     /// Default Level of every user
     Active,
@@ -36,9 +84,23 @@ impl UserStatus {
     pub fn is_active(&self) -> bool {
         *self == Self::Active
     }
+
+    /// active users can leave, inactive ones can come back, resigned and terminated users are final
+    pub fn can_change_to(&self, next: &Self) -> bool {
+        matches!(
+            (self, next),
+            (
+                Self::Active,
+                Self::Resigned | Self::Terminated | Self::Inactive
+            ) | (
+                Self::Inactive,
+                Self::Active | Self::Resigned | Self::Terminated
+            )
+        )
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, sqlx::Type, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::Type, PartialEq, AsRefStr)]
 pub enum State {
     // US States
     AL,
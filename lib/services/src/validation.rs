@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::i18n::{t, t_args};
+
+/// Validation failures keyed by field path, e.g. `email`, `card.token` or `items[0].name`.
+pub type FieldErrors = BTreeMap<String, Vec<FieldError>>;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// validator that failed, e.g. `email`, `length` or the name of a custom one
+    pub code: String,
+    pub message: String,
+    pub params: Map<String, Value>,
+}
+
+impl From<&ValidationError> for FieldError {
+    fn from(error: &ValidationError) -> Self {
+        // the submitted value is left out, it could be a password
+        let params: Map<String, Value> = error
+            .params
+            .iter()
+            .filter(|(name, _)| *name != "value")
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        // custom messages of the validator attributes are message ids, e.g. `passwords-mismatch`
+        let message = match &error.message {
+            Some(message) => t(message),
+            None => default_message(&error.code, &params),
+        };
+        Self {
+            code: error.code.to_string(),
+            message,
+            params,
+        }
+    }
+}
+
+/// codes of the validators and database checks with their own `validation-<code>` message,
+/// the others get `validation-invalid`
+pub const VALIDATION_CODES: [&str; 9] = [
+    "email",
+    "phone",
+    "url",
+    "length",
+    "range",
+    "required",
+    "must_match",
+    "unique",
+    "exists",
+];
+
+/// `validation-<code>` message of the catalogue, with the params as arguments
+fn default_message(code: &str, params: &Map<String, Value>) -> String {
+    if !VALIDATION_CODES.contains(&code) {
+        return t("validation-invalid");
+    }
+    let id = format!("validation-{code}");
+    let args: Vec<(&str, String)> = params
+        .iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => (name.as_str(), value.clone()),
+            value => (name.as_str(), value.to_string()),
+        })
+        .collect();
+    t_args(&id, &args)
+}
+
+/// flatten the nested errors of `validator` into field paths
+pub fn field_errors(errors: &ValidationErrors) -> FieldErrors {
+    let mut fields = FieldErrors::new();
+    collect(errors, "", &mut fields);
+    fields
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, fields: &mut FieldErrors) {
+    for (field, kind) in errors.errors() {
+        let path = match prefix {
+            "" => field.to_string(),
+            prefix => format!("{prefix}.{field}"),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => fields
+                .entry(path)
+                .or_default()
+                .extend(errors.iter().map(FieldError::from)),
+            ValidationErrorsKind::Struct(errors) => collect(errors, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect(errors, &format!("{path}[{index}]"), fields);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use validator::Validate;
+
+    use super::field_errors;
+
+    #[derive(Deserialize, Validate)]
+    struct Item {
+        #[validate(length(min = 1))]
+        name: String,
+    }
+
+    #[derive(Deserialize, Validate)]
+    struct Form {
+        #[validate(email)]
+        email: String,
+        #[validate]
+        item: Item,
+        #[validate]
+        items: Vec<Item>,
+    }
+
+    #[test]
+    fn should_flatten_nested_fields() {
+        let form = Form {
+            email: "secret".into(),
+            item: Item { name: "".into() },
+            items: vec![Item { name: "ok".into() }, Item { name: "".into() }],
+        };
+        let fields = field_errors(&form.validate().unwrap_err());
+        let paths: Vec<&str> = fields.keys().map(String::as_str).collect();
+        assert_eq!(paths, vec!["email", "item.name", "items[1].name"]);
+        assert_eq!(fields["email"][0].code, "email");
+        assert_eq!(fields["email"][0].message, "Must be a valid email");
+        assert!(fields["email"][0].params.get("value").is_none());
+        assert_eq!(fields["item.name"][0].params["min"], 1);
+    }
+}
@@ -0,0 +1,22 @@
+-- users need a primary key to be referenced by the tables below
+ALTER TABLE "users"
+    ADD PRIMARY KEY (id);
+-- phone numbers are validated in E.164 format (e.g. +17786866393)
+ALTER TABLE "users"
+    ALTER COLUMN phone TYPE VARCHAR(20);
+ALTER TABLE "users"
+    ADD COLUMN phone_verified_at TIMESTAMP;
+
+CREATE TABLE IF NOT EXISTS "one_time_passcodes"
+(
+    id         SERIAL PRIMARY KEY,
+    user_id    INT          NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    purpose    VARCHAR(50)  NOT NULL, -- [Login, VerifyPhone]
+    code       TEXT         NOT NULL, -- bcrypt hash of the code sent by sms
+    attempts   INT          NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP    NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS one_time_passcodes_user_purpose_idx ON one_time_passcodes (user_id, purpose, created_at);
@@ -0,0 +1,7 @@
+ALTER TABLE "users"
+    ADD COLUMN email_verified_at          TIMESTAMP,
+    ADD COLUMN email_verification_sent_at TIMESTAMP;
+
+-- accounts created before verification existed are trusted
+UPDATE "users"
+SET email_verified_at = created_at;
@@ -0,0 +1,3 @@
+CREATE UNIQUE INDEX IF NOT EXISTS users_user_name_key ON users (user_name);
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_key ON users (email);
+CREATE UNIQUE INDEX IF NOT EXISTS users_phone_key ON users (phone);
@@ -0,0 +1,15 @@
+ALTER TABLE "users"
+    ADD COLUMN failed_login_count   INT NOT NULL DEFAULT 0,
+    ADD COLUMN last_failed_login_at TIMESTAMP,
+    ADD COLUMN locked_until         TIMESTAMP;
+
+CREATE TABLE IF NOT EXISTS "login_attempts"
+(
+    id         SERIAL PRIMARY KEY,
+    user_name  VARCHAR(100) NOT NULL,
+    ip         VARCHAR(45),
+    success    BOOLEAN      NOT NULL,
+    created_at TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS login_attempts_ip_idx ON login_attempts (ip, created_at);
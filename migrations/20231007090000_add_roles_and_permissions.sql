@@ -0,0 +1,43 @@
+CREATE TABLE IF NOT EXISTS "roles"
+(
+    id         SERIAL PRIMARY KEY,
+    name       VARCHAR(100) NOT NULL UNIQUE,
+    created_at TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS "permissions"
+(
+    id          SERIAL PRIMARY KEY,
+    name        VARCHAR(100) NOT NULL UNIQUE,
+    description TEXT         NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "role_permissions"
+(
+    role_id       INT NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    permission_id INT NOT NULL REFERENCES permissions (id) ON DELETE CASCADE,
+    PRIMARY KEY (role_id, permission_id)
+);
+
+-- extra roles, every user also has the role named after their type
+CREATE TABLE IF NOT EXISTS "user_roles"
+(
+    user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role_id INT NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_id)
+);
+
+INSERT INTO roles (name)
+VALUES ('Admin'),
+       ('Associate');
+
+INSERT INTO permissions (name, description)
+VALUES ('ama.read', 'View AMA records'),
+       ('ama.write', 'Create, update and delete AMA records');
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+         CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+   OR (r.name = 'Associate' AND p.name = 'ama.read');
@@ -0,0 +1,2 @@
+ALTER TABLE "ama"
+    ADD COLUMN created_by INT REFERENCES users (id) ON DELETE SET NULL;
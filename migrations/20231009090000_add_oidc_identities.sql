@@ -0,0 +1,22 @@
+-- external identities (OpenID Connect) linked to local users
+CREATE TABLE IF NOT EXISTS "user_identities"
+(
+    id         SERIAL PRIMARY KEY,
+    user_id    INT          NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    issuer     VARCHAR(255) NOT NULL,
+    subject    VARCHAR(255) NOT NULL, -- `sub` claim of the id token
+    created_at TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_idx ON user_identities (user_id);
+
+-- pending authorization requests, consumed by the callback
+CREATE TABLE IF NOT EXISTS "oidc_login_requests"
+(
+    state         VARCHAR(64)  PRIMARY KEY,
+    nonce         VARCHAR(64)  NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL, -- PKCE verifier, only its challenge is sent to the provider
+    expires_at    TIMESTAMP    NOT NULL,
+    created_at    TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,25 @@
+-- keys for machine to machine clients, they act as `user_id` limited to `scopes`
+CREATE TABLE IF NOT EXISTS "api_keys"
+(
+    id           SERIAL PRIMARY KEY,
+    name         VARCHAR(100) NOT NULL,
+    prefix       VARCHAR(16)  NOT NULL UNIQUE, -- shown to identify the key, the rest is only known to the client
+    key_hash     TEXT         NOT NULL UNIQUE, -- sha256 of the full key
+    user_id      INT          NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    scopes       TEXT[]       NOT NULL DEFAULT '{}',
+    expires_at   TIMESTAMP,
+    last_used_at TIMESTAMP,
+    revoked_at   TIMESTAMP,
+    created_by   INT REFERENCES users (id) ON DELETE SET NULL,
+    created_at   TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO permissions (name, description)
+VALUES ('api_keys.manage', 'Create, list and revoke API keys');
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+         CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+  AND p.name = 'api_keys.manage';
@@ -0,0 +1,23 @@
+-- audit of admins signing in as other users
+CREATE TABLE IF NOT EXISTS "impersonations"
+(
+    id         SERIAL PRIMARY KEY,
+    admin_id   INT       NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    user_id    INT       NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    session_id INT       NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+    reason     TEXT,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at   TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS impersonations_session_id_idx ON impersonations (session_id);
+
+INSERT INTO permissions (name, description)
+VALUES ('users.impersonate', 'Sign in as another user');
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+         CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+  AND p.name = 'users.impersonate';
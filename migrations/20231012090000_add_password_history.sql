@@ -0,0 +1,14 @@
+-- hashes of previous passwords, checked so users don't reuse them
+CREATE TABLE IF NOT EXISTS "password_history"
+(
+    id            SERIAL PRIMARY KEY,
+    user_id       INT       NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    password_hash TEXT      NOT NULL,
+    created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS password_history_user_id_idx ON password_history (user_id, created_at);
+
+INSERT INTO password_history (user_id, password_hash)
+SELECT id, password
+FROM users;
@@ -0,0 +1,23 @@
+-- history of user status transitions, e.g. Active -> Terminated
+CREATE TABLE IF NOT EXISTS "user_status_changes"
+(
+    id          SERIAL PRIMARY KEY,
+    user_id     INT          NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    from_status VARCHAR(255) NOT NULL,
+    to_status   VARCHAR(255) NOT NULL,
+    reason      TEXT         NOT NULL,
+    changed_by  INT REFERENCES users (id) ON DELETE SET NULL,
+    created_at  TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS user_status_changes_user_id_idx ON user_status_changes (user_id);
+
+INSERT INTO permissions (name, description)
+VALUES ('users.manage', 'List, create, update and change the status of users');
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+         CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+  AND p.name = 'users.manage';
@@ -0,0 +1,11 @@
+-- state of the rate limits shared by every instance, see `services::rate_limit::postgres`
+CREATE UNLOGGED TABLE IF NOT EXISTS "rate_limits"
+(
+    key        TEXT PRIMARY KEY,
+    value      DOUBLE PRECISION NOT NULL,
+    previous   DOUBLE PRECISION NOT NULL,
+    updated_at DOUBLE PRECISION NOT NULL, -- unix time in seconds
+    expires_at DOUBLE PRECISION NOT NULL  -- unix time in seconds
+);
+
+CREATE INDEX IF NOT EXISTS rate_limits_expires_at_idx ON rate_limits (expires_at);
@@ -0,0 +1,3 @@
+-- language of the user's emails and error messages, NULL follows the browser's Accept-Language
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS locale VARCHAR(255);
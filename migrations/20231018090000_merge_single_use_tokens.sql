@@ -0,0 +1,25 @@
+-- the links emailed to users share one table, like the one time passcodes
+CREATE TABLE IF NOT EXISTS "single_use_tokens"
+(
+    id         SERIAL PRIMARY KEY,
+    user_id    INT         NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    purpose    VARCHAR(50) NOT NULL, -- [PasswordReset, MagicLink, EmailVerification]
+    token_hash TEXT        NOT NULL UNIQUE, -- sha256 of the token sent by email
+    expires_at TIMESTAMP   NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS single_use_tokens_user_purpose_idx ON single_use_tokens (user_id, purpose);
+
+-- links already sent keep working
+INSERT INTO single_use_tokens (user_id, purpose, token_hash, expires_at, used_at, created_at)
+SELECT user_id, 'PasswordReset', token_hash, expires_at, used_at, created_at FROM password_reset_tokens;
+INSERT INTO single_use_tokens (user_id, purpose, token_hash, expires_at, used_at, created_at)
+SELECT user_id, 'MagicLink', token_hash, expires_at, used_at, created_at FROM magic_link_tokens;
+INSERT INTO single_use_tokens (user_id, purpose, token_hash, expires_at, used_at, created_at)
+SELECT user_id, 'EmailVerification', token_hash, expires_at, used_at, created_at FROM email_verification_tokens;
+
+DROP TABLE password_reset_tokens;
+DROP TABLE magic_link_tokens;
+DROP TABLE email_verification_tokens;
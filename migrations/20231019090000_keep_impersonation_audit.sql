@@ -0,0 +1,14 @@
+-- the audit of impersonations outlives the admins, users and sessions it mentions
+ALTER TABLE impersonations
+    ALTER COLUMN admin_id DROP NOT NULL,
+    ALTER COLUMN user_id DROP NOT NULL,
+    ALTER COLUMN session_id DROP NOT NULL,
+    DROP CONSTRAINT impersonations_admin_id_fkey,
+    DROP CONSTRAINT impersonations_user_id_fkey,
+    DROP CONSTRAINT impersonations_session_id_fkey,
+    ADD CONSTRAINT impersonations_admin_id_fkey
+        FOREIGN KEY (admin_id) REFERENCES users (id) ON DELETE SET NULL,
+    ADD CONSTRAINT impersonations_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE SET NULL,
+    ADD CONSTRAINT impersonations_session_id_fkey
+        FOREIGN KEY (session_id) REFERENCES sessions (id) ON DELETE SET NULL;